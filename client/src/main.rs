@@ -1,37 +1,160 @@
-use std::{env, io::{self, Write}, net::TcpStream};
+use std::{env, io::{self, Read, Write}, net::{Shutdown, TcpStream}, path::Path};
 
-use log::LevelFilter;
+use log::{error, LevelFilter};
 
 use common::{
-	group::SerializedTaskGroup, log::SimpleLogger, queries::Queries
+	at::AtRequest, command::Command, group::SerializedTaskGroup, log::SimpleLogger, queries::Queries, secrets
 };
 
 pub static LOGGER: SimpleLogger = SimpleLogger;
 
-fn main() -> io::Result<()> {
-	log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Info))
-		.unwrap();
+const USAGE: &str = "Usage: ./client [PATH TO CONFIG]
+       ./client seal-secrets [KEY FILE] [SECRETS JSON] [OUTPUT]
+       ./client stats [GROUP]
+       ./client at TIME [--within SECONDS] [--name NAME] -- PROGRAM [ARGS...]
+       ./client atq
+       ./client atrm ID";
+
+fn send(query: &Queries) -> io::Result<Queries> {
+    let formatted_query = serde_json::to_string(query).unwrap();
 
-    let conf_path: Vec<String> = env::args().collect();
-    if conf_path.len() != 2 {
-        panic!("Usage: ./client [PATH TO CONFIG]");
+    let mut stream = TcpStream::connect("127.0.0.1:65533")?;
+    stream.write_all(formatted_query.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    match serde_json::from_str(answer.as_str()) {
+    Ok(Queries::Error(e)) => {
+        error!("The server rejected the query: {}", e);
+        std::process::exit(1);
+    },
+    Ok(answer) => Ok(answer),
+    Err(_) => Err(io::Error::from(io::ErrorKind::InvalidData))
     }
+}
 
+fn new_task_group(conf_path: &str) -> io::Result<()> {
     let task_group: SerializedTaskGroup = serde_json::from_str(
-        &String::from_utf8(
-            std::fs::read(&conf_path[1])
+        String::from_utf8(
+            std::fs::read(conf_path)
                 .unwrap()
         ).unwrap()
         .as_str()
     ).unwrap();
 
-    let formatted_conf =
-        serde_json::to_string(
-            &Queries::NewTaskGroup(task_group)
-        ).unwrap();
-    
-    let mut stream = TcpStream::connect("127.0.0.1:65533")?;
-    stream.write(formatted_conf.as_bytes())?;
+    send(&Queries::NewTaskGroup(Box::new(task_group)))?;
     Ok(())
 }
+
+fn seal_secrets(key: &str, input: &str, output: &str) -> io::Result<()> {
+    let sealed = secrets::seal(Path::new(key), &std::fs::read(input)?)?;
+    std::fs::write(output, sealed)
+}
+
+fn print_statistics(group: Option<&str>) -> io::Result<()> {
+    match send(&Queries::GetStatistics(group.map(String::from)))? {
+    Queries::Statistics(reports) => {
+        for report in reports {
+            println!("\"{}\" / \"{}\"", report.group, report.task);
+            println!("{}\n", report.statistics);
+        }
+        Ok(())
+    },
+    _ => Err(io::Error::from(io::ErrorKind::InvalidData))
+    }
+}
+
+/* The command runs in the current directory */
+fn at(args: &[&str]) -> io::Result<()> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, USAGE));
+
+    let (options, command) = match args.iter().position(|x| *x == "--") {
+    Some(idx) => (&args[.. idx], &args[idx + 1 ..]),
+    None => return Err(invalid("Missing the command"))
+    };
+    let Some((program, arguments)) = command.split_first() else {
+        return Err(invalid("Missing the command"));
+    };
+
+    let mut time = None;
+    let mut within = None;
+    let mut name = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+        "--within" => within = Some(options.next()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| invalid("Invalid delay"))?),
+        "--name" => name = Some(options.next()
+            .ok_or_else(|| invalid("Missing the name"))?
+            .to_string()),
+        x if time.is_none() => time = Some(x.to_string()),
+        x => return Err(invalid(&format!("Unexpected argument: {}", x)))
+        }
+    }
+
+    let cmd: Command = serde_json::from_value(serde_json::json!({
+        "program": program,
+        "args": arguments,
+        "chdir": env::current_dir()?
+    }))?;
+    let request = AtRequest {
+        time: time.ok_or_else(|| invalid("Missing the time"))?,
+        within,
+        name,
+        cmd
+    };
+
+    match send(&Queries::At(Box::new(request)))? {
+    Queries::AtQueued(id) => {
+        println!("Job {} queued", id);
+        Ok(())
+    },
+    _ => Err(io::Error::from(io::ErrorKind::InvalidData))
+    }
+}
+
+fn print_at_queue() -> io::Result<()> {
+    match send(&Queries::ListAt)? {
+    Queries::AtJobs(jobs) => {
+        for job in jobs {
+            let state = if job.running {
+                String::from("running")
+            } else {
+                job.fire_time.map_or(String::from("-"), |x| x.to_rfc3339())
+            };
+            println!("{}\t{}\t{}\t{}\t{}", job.id, job.time.to_rfc3339(), state, job.name, job.command);
+        }
+        Ok(())
+    },
+    _ => Err(io::Error::from(io::ErrorKind::InvalidData))
+    }
+}
+
+fn cancel_at(id: &str) -> io::Result<()> {
+    let id = id.parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid job: {}", id)))?;
+    send(&Queries::CancelAt(id))?;
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+	log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LevelFilter::Info))
+		.unwrap();
+
+    let args: Vec<String> = env::args().collect();
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    match args[1 ..] {
+    ["seal-secrets", key, input, output] => seal_secrets(key, input, output),
+    ["stats"] => print_statistics(None),
+    ["stats", group] => print_statistics(Some(group)),
+    ["at", ref rest @ ..] => at(rest),
+    ["atq"] => print_at_queue(),
+    ["atrm", id] => cancel_at(id),
+    [conf_path] => new_task_group(conf_path),
+    _ => panic!("{}", USAGE)
+    }
+}
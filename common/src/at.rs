@@ -0,0 +1,104 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{command::Command, hooks::Hooks, task::TaskConfig, utils::{get_period_from_string, get_start_timestamp_from_string}};
+
+/* One-shot execution asked to the server */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AtRequest {
+    /* See parse_time */
+    pub time: String,
+    /* The execution is delayed at random, up to this many seconds */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub cmd: Command,
+}
+
+/* Job of the queue, as listed by the server */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AtJobSummary {
+    pub id: u64,
+    pub name: String,
+    pub time: DateTime<Utc>,
+    /* When it will actually run, once the random delay is drawn */
+    pub fire_time: Option<DateTime<Utc>>,
+    pub running: bool,
+    pub command: String,
+}
+
+/* Accepts "now", a delay from now ("now + 1h", "+PT30M"), the start dates
+ * of the groups, RFC 3339 dates, and dates down to the minute
+ * ("2026-11-01T03:00Z", "2026-11-01 03:00"), taken as UTC. The job becomes
+ * a group starting at that time, so the year must fit in four digits.
+ */
+pub fn parse_time(time: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    parse_any_time(time, now)
+        .filter(|x| (0 ..= 9999).contains(&x.year()))
+}
+
+fn parse_any_time(time: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let time = time.trim();
+    if time == "now" {
+        return Some(now);
+    }
+
+    let delay = time.strip_prefix("now")
+        .map(str::trim_start)
+        .unwrap_or(time)
+        .strip_prefix('+');
+    if let Some(delay) = delay {
+        return get_period_from_string(delay)?.nth(now, 1);
+    }
+
+    if let Some(x) = get_start_timestamp_from_string(time) {
+        return Some(x);
+    }
+    if let Ok(x) = DateTime::parse_from_rfc3339(time) {
+        return Some(x.to_utc());
+    }
+
+    let time = time.strip_suffix('Z').unwrap_or(time);
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .map(|x| x.and_utc())
+}
+
+impl AtRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if parse_time(&self.time, Utc::now()).is_none() {
+            return Err(format!("Invalid time: {}", self.time));
+        }
+        self.cmd.validate()
+    }
+
+    /* What the job runs, for the listings */
+    pub fn describe(&self) -> String {
+        match &self.cmd.command {
+        Some(program) => std::iter::once(program)
+            .chain(self.cmd.arguments.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => String::from("(script)")
+        }
+    }
+
+    pub fn task(&self) -> TaskConfig {
+        TaskConfig {
+            name: self.name.clone(),
+            cmd: self.cmd.clone(),
+            max_concurrent_execution: None,
+            depends_on: Vec::new(),
+            retries: None,
+            retry_delay: None,
+            run_when: None,
+            hooks: Hooks::default(),
+            stdout_path: None,
+            stderr_path: None,
+            cgroup_path: None,
+            secrets: None,
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/* Bound on the intervals chained together when looking for the end of a
+ * window, in case the calendar covers all the time */
+const MAX_STEPS: usize = 1000;
+
+/* Start included, end excluded */
+pub type Interval = (DateTime<Utc>, DateTime<Utc>);
+
+/* Start of an event being parsed, and whether it is a whole day */
+type EventStart = Option<(DateTime<Utc>, bool)>;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/* Every week, between two times of the day (UTC). "to" may be before
+ * "from" for ranges spanning midnight, and may be 24:00.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct WeeklyRange {
+    pub days: Vec<Weekday>,
+    pub from: String,
+    pub to: String,
+}
+
+/* Dates are whole days, "to" being included. Datetimes are RFC 3339 */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DateRange {
+    pub from: String,
+    pub to: String,
+}
+
+/* Named set of time intervals, referred to by the groups. All times are
+ * UTC. The files are read when the calendar is loaded.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Calendar {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekly: Vec<WeeklyRange>,
+    /* Whole days, YYYY-MM-DD */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dates: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<DateRange>,
+    /* Holiday lists: one YYYY-MM-DD date per line, followed by anything */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub date_files: Vec<PathBuf>,
+    /* The events of iCalendar files. Recurrences are not supported */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ics_files: Vec<PathBuf>,
+
+    #[serde(skip)]
+    weekly_times: Vec<(Vec<Weekday>, NaiveTime, Duration)>,
+    #[serde(skip)]
+    intervals: Vec<Interval>,
+}
+
+/* What to do with occurrences the calendars don't allow */
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlackoutPolicy {
+    #[default]
+    Skip,
+    /* Runs at the start of the next allowed period */
+    Defer,
+}
+
+/* Calendars a group's occurrences must be in, or out of */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CalendarConstraints {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only_during: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub never_during: Option<String>,
+    #[serde(default)]
+    pub blackout_policy: BlackoutPolicy,
+}
+
+impl CalendarConstraints {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.only_during.as_deref().into_iter()
+            .chain(self.never_during.as_deref())
+    }
+
+    /* When the occurrence may run, if ever, or the unknown calendar */
+    pub fn next_allowed(&self, time: DateTime<Utc>, calendars: &Calendars) -> Result<Option<DateTime<Utc>>, String> {
+        let get = |name: &Option<String>| match name {
+            Some(name) => calendars.get(name)
+                .map(Some)
+                .ok_or_else(|| format!("Unknown calendar: {}", name)),
+            None => Ok(None)
+        };
+
+        Ok(next_allowed(time, get(&self.only_during)?, get(&self.never_during)?))
+    }
+}
+
+pub type Calendars = HashMap<String, Calendar>;
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    if time == "24:00" {
+        return Some(NaiveTime::MIN);
+    }
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+fn parse_date(date: &str) -> io::Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| invalid_data(format!("Invalid date: {}", date)))
+}
+
+fn whole_day(date: NaiveDate) -> Interval {
+    let start = date.and_time(NaiveTime::MIN).and_utc();
+    (start, start + Duration::days(1))
+}
+
+/* A date stands for its start, or its end when it ends a range */
+fn parse_bound(bound: &str, end: bool) -> io::Result<DateTime<Utc>> {
+    if let Ok(x) = DateTime::parse_from_rfc3339(bound) {
+        return Ok(x.to_utc());
+    }
+    let (start, stop) = whole_day(parse_date(bound)?);
+    Ok(if end { stop } else { start })
+}
+
+fn parse_ics_time(value: &str) -> Option<(DateTime<Utc>, bool)> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Some((whole_day(date).0, true));
+    }
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+        .map(|x| (x.and_utc(), false))
+}
+
+/* Floating times and TZID parameters are taken as UTC */
+pub fn parse_ics(content: &str) -> Result<Vec<Interval>, String> {
+    /* Long lines are folded, continuation lines starting with a blank */
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+        (Some(rest), Some(last)) => last.push_str(rest),
+        _ => lines.push(line.to_string())
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut event: Option<(EventStart, Option<DateTime<Utc>>)> = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.split(';').next().unwrap().to_ascii_uppercase();
+
+        match (name.as_str(), value.trim(), &mut event) {
+        ("BEGIN", "VEVENT", _) => event = Some((None, None)),
+        ("END", "VEVENT", Some((start, end))) => {
+            let (start, all_day) = start
+                .ok_or_else(|| String::from("Event without DTSTART"))?;
+            let end = end.unwrap_or(
+                if all_day { start + Duration::days(1) } else { start }
+            );
+            out.push((start, end));
+            event = None;
+        },
+        ("DTSTART", value, Some((start, _))) => *start = Some(parse_ics_time(value)
+            .ok_or_else(|| format!("Invalid DTSTART: {}", value))?),
+        ("DTEND", value, Some((_, end))) => *end = Some(parse_ics_time(value)
+            .ok_or_else(|| format!("Invalid DTEND: {}", value))?.0),
+        ("RRULE", _, Some(_)) => warn!("Recurring events are only taken once"),
+        _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+fn read_date_file(path: &Path) -> io::Result<Vec<NaiveDate>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_date(line.split_whitespace().next().unwrap()))
+        .collect()
+}
+
+impl Calendar {
+    /* Parses the definitions and reads the files */
+    pub fn load(&mut self) -> io::Result<()> {
+        self.weekly_times.clear();
+        for range in self.weekly.iter() {
+            let (from, to) = match (parse_time(&range.from), parse_time(&range.to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err(invalid_data(format!("Invalid time range: {} - {}", range.from, range.to)))
+            };
+            let mut length = to - from;
+            if length <= Duration::zero() {
+                length += Duration::days(1);
+            }
+            self.weekly_times.push((range.days.clone(), from, length));
+        }
+
+        let mut intervals = Vec::new();
+        for date in self.dates.iter() {
+            intervals.push(whole_day(parse_date(date)?));
+        }
+        for range in self.ranges.iter() {
+            intervals.push((parse_bound(&range.from, false)?, parse_bound(&range.to, true)?));
+        }
+        for path in self.date_files.iter() {
+            let dates = read_date_file(path)
+                .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+            intervals.extend(dates.into_iter().map(whole_day));
+        }
+        for path in self.ics_files.iter() {
+            let events = parse_ics(&fs::read_to_string(path)?)
+                .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+            intervals.extend(events);
+        }
+        self.intervals = intervals;
+
+        Ok(())
+    }
+
+    /* The intervals that may contain times between from and to */
+    fn intervals_around(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Interval> {
+        let mut out: Vec<_> = self.intervals.iter()
+            .filter(|(start, end)| *start <= to && *end > from)
+            .copied()
+            .collect();
+
+        /* Weekly ranges are at most a day long, and may start the day
+         * before */
+        let mut day = from.date_naive() - Duration::days(1);
+        while day <= to.date_naive() {
+            for (days, time, length) in self.weekly_times.iter() {
+                if days.contains(&day.weekday()) {
+                    let start = day.and_time(*time).and_utc();
+                    out.push((start, start + *length));
+                }
+            }
+            day += Duration::days(1);
+        }
+
+        out
+    }
+
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.intervals_around(time, time)
+            .iter()
+            .any(|(start, end)| *start <= time && time < *end)
+    }
+
+    /* First time not in the calendar, from the given one */
+    pub fn end_of_window(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut current = time;
+        for _ in 0 .. MAX_STEPS {
+            let end = self.intervals_around(current, current)
+                .iter()
+                .filter(|(start, end)| *start <= current && current < *end)
+                .map(|(_, end)| *end)
+                .max();
+            match end {
+            Some(end) => current = end,
+            None => return Some(current)
+            }
+        }
+        None
+    }
+
+    /* First time in the calendar, from the given one */
+    pub fn start_of_window(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.contains(time) {
+            return Some(time);
+        }
+
+        let weekly_start = self.intervals_around(time, time + Duration::days(8))
+            .iter()
+            .map(|(start, _)| *start)
+            .filter(|start| *start >= time)
+            .min();
+        let later_start = self.intervals.iter()
+            .map(|(start, _)| *start)
+            .filter(|start| *start >= time)
+            .min();
+
+        match (weekly_start, later_start) {
+        (Some(x), Some(y)) => Some(x.min(y)),
+        (x, y) => x.or(y)
+        }
+    }
+}
+
+/* First time, from the given one, inside only_during and outside
+ * never_during */
+pub fn next_allowed(
+    time: DateTime<Utc>,
+    only_during: Option<&Calendar>,
+    never_during: Option<&Calendar>
+) -> Option<DateTime<Utc>> {
+    let mut current = time;
+    for _ in 0 .. MAX_STEPS {
+        if let Some(calendar) = only_during
+            && !calendar.contains(current) {
+            current = calendar.start_of_window(current)?;
+            continue;
+        }
+        if let Some(calendar) = never_during
+            && calendar.contains(current) {
+            current = calendar.end_of_window(current)?;
+            continue;
+        }
+        return Some(current);
+    }
+    None
+}
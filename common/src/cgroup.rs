@@ -0,0 +1,159 @@
+use std::{
+    fs::{self, File, OpenOptions}, io, os::fd::{AsRawFd, RawFd}, path::{Path, PathBuf}, thread, time::{Duration, Instant}
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/* Values written verbatim to the matching cgroup v2 interface files,
+ * e.g. "512M" for memory.max or "50000 100000" for cpu.max.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CgroupSettings {
+    pub memory_max: Option<String>,
+    pub cpu_max: Option<String>,
+    pub pids_max: Option<String>,
+}
+
+/* Creates a directory inside a delegated cgroup v2 hierarchy, and makes
+ * the cpu, memory and pids controllers available to its children.
+ */
+pub fn create_parent(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        fs::create_dir(path)?;
+    }
+
+    let available = fs::read_to_string(path.join("cgroup.controllers"))?;
+    let enabled: Vec<String> = available.split_whitespace()
+        .filter(|x| CONTROLLERS.contains(x))
+        .map(|x| format!("+{}", x))
+        .collect();
+
+    if !enabled.is_empty() {
+        fs::write(path.join("cgroup.subtree_control"), enabled.join(" "))?;
+    }
+
+    Ok(())
+}
+
+/* A cgroup v2 can only distribute resources to its children if no process
+ * lives in it, so the server moves itself away from the delegated root.
+ */
+pub fn setup_root(path: &Path) -> io::Result<()> {
+    let procs = fs::read_to_string(path.join("cgroup.procs"))?;
+    if !procs.trim().is_empty() {
+        let server = path.join("server");
+        if !server.exists() {
+            fs::create_dir(&server)?;
+        }
+        for pid in procs.lines() {
+            fs::write(server.join("cgroup.procs"), pid)?;
+        }
+    }
+
+    create_parent(path)
+}
+
+/* A single-use cgroup holding one execution. */
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File
+}
+
+impl Cgroup {
+    pub fn create(path: &Path, settings: Option<&CgroupSettings>) -> io::Result<Self> {
+        if !path.exists() {
+            fs::create_dir(path)?;
+        }
+
+        if let Some(settings) = settings {
+            for (file, value) in [
+                ("memory.max", &settings.memory_max),
+                ("cpu.max", &settings.cpu_max),
+                ("pids.max", &settings.pids_max)
+            ] {
+                if let Some(value) = value {
+                    fs::write(path.join(file), value)?;
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            procs: OpenOptions::new()
+                .write(true)
+                .open(path.join("cgroup.procs"))?
+        })
+    }
+
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    pub fn kill_path(&self) -> PathBuf {
+        self.path.join("cgroup.kill")
+    }
+
+    pub fn memory_peak(&self) -> Option<u64> {
+        fs::read_to_string(self.path.join("memory.peak")).ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    pub fn oom_killed(&self) -> bool {
+        read_key(&self.path.join("memory.events"), "oom_kill")
+            .is_some_and(|x| x > 0)
+    }
+
+    fn is_populated(&self) -> bool {
+        read_key(&self.path.join("cgroup.events"), "populated")
+            .is_none_or(|x| x > 0)
+    }
+
+    /* Kills whatever the execution left behind, then removes the cgroup */
+    pub fn remove(self) {
+        if self.is_populated() {
+            if let Err(e) = kill(&self.kill_path()) {
+                warn!("Unable to kill the leftovers of {:?}: {}", self.path, e);
+            }
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while self.is_populated() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        drop(self.procs);
+        if let Err(e) = fs::remove_dir(&self.path) {
+            warn!("Unable to remove {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/* Moves the calling process into the cgroup. Meant to be called from a
+ * pre_exec hook, hence the raw write(2).
+ */
+pub fn join(procs: RawFd) -> io::Result<()> {
+    let ret = unsafe { libc::write(procs, b"0".as_ptr() as *const libc::c_void, 1) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn kill(kill_path: &Path) -> io::Result<()> {
+    fs::write(kill_path, "1")
+}
+
+fn read_key(path: &Path, key: &str) -> Option<u64> {
+    fs::read_to_string(path).ok()?
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| v.trim().parse().ok())
+}
@@ -1,9 +1,13 @@
 use std::{
-	collections::HashMap, convert::Infallible, io, ops::{ControlFlow, FromResidual, Try}, os::unix::process::CommandExt, path::PathBuf, process::ExitStatus, sync::PoisonError, time::{Duration, Instant}
+	collections::{BTreeMap, HashMap}, convert::Infallible, io::{self, Read, Write}, ops::{ControlFlow, FromResidual, Try}, os::unix::process::{CommandExt, ExitStatusExt}, path::PathBuf, process::{ExitStatus, Stdio}, sync::{mpsc::{self, RecvTimeoutError}, Arc, PoisonError}, thread, time::{Duration, Instant}
 };
 
+use chrono::{DateTime, Utc};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
+use crate::{cgroup::{self, Cgroup, CgroupSettings}, env::{self, RunContext}, limits::Limits, sandbox::Sandbox, script::Script, secrets, success::SuccessCriteria, template::{self, Variables}, user::{self, Credentials, User}};
+
 #[derive(Debug)]
 pub enum Log {
 	Buffer(Vec<u8>),
@@ -23,18 +27,63 @@ impl Log {
 	}
 }
 
+/* Resources consumed by a single execution, as reported by wait4(2).
+ * Sizes are in kilobytes, like in getrusage(2), except for the cgroup
+ * memory peak which is in bytes.
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceUsage {
+	pub user_time: Duration,
+	pub system_time: Duration,
+	pub max_rss: u64,
+	pub block_input: u64,
+	pub block_output: u64,
+	pub voluntary_context_switches: u64,
+	pub involuntary_context_switches: u64,
+	pub memory_peak: Option<u64>
+}
+
+impl From<&libc::rusage> for ResourceUsage {
+	fn from(usage: &libc::rusage) -> Self {
+		fn timeval_to_duration(tv: &libc::timeval) -> Duration {
+			Duration::new(tv.tv_sec as u64, 1000 * tv.tv_usec as u32)
+		}
+
+		Self {
+			user_time: timeval_to_duration(&usage.ru_utime),
+			system_time: timeval_to_duration(&usage.ru_stime),
+			max_rss: usage.ru_maxrss as u64,
+			block_input: usage.ru_inblock as u64,
+			block_output: usage.ru_oublock as u64,
+			voluntary_context_switches: usage.ru_nvcsw as u64,
+			involuntary_context_switches: usage.ru_nivcsw as u64,
+			memory_peak: None
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct CommandOutcome {
 	pub exit_status: ExitStatus,
 	pub stdout: Log,
 	pub stderr: Log,
 	pub start: Instant,
-	pub duration: Duration
+	pub duration: Duration,
+	pub usage: ResourceUsage,
+	/* As decided by the success criteria of the command */
+	pub success: bool,
+	/* SHA-256 of the inline script that ran, if any */
+	pub script_hash: Option<String>,
+	/* What the execution wrote in its outputs file */
+	pub outputs: BTreeMap<String, String>,
+	/* Nominal time of the occurrence, and when the execution started */
+	pub scheduled_time: DateTime<Utc>,
+	pub fire_time: DateTime<Utc>
 }
 
 impl CommandOutcome {
 	pub fn is_success(&self) -> bool {
-		self.exit_status.success()
+		self.success
 	}
 }
 
@@ -44,7 +93,9 @@ pub enum TaskOutput {
 	Waiting,
     IOError(io::Error),
 	PoisonError,
-	TooManyThreadsError
+	TooManyThreadsError,
+	TimedOut(CommandOutcome),
+	OutOfMemory(CommandOutcome)
 }
 
 impl TaskOutput {
@@ -52,9 +103,11 @@ impl TaskOutput {
         match self {
             TaskOutput::NoError(_) => String::from("NoError"),
 			TaskOutput::Waiting => String::from("Waiting"),
-            TaskOutput::IOError(e) => format!("IOError ({})", e.to_string()),
+            TaskOutput::IOError(e) => format!("IOError ({})", e),
 			TaskOutput::PoisonError => String::from("PoisonError"),
 			TaskOutput::TooManyThreadsError => String::from("TooManyThreadsError"),
+			TaskOutput::TimedOut(_) => String::from("TimedOut"),
+			TaskOutput::OutOfMemory(_) => String::from("OutOfMemory"),
         }
     }
 
@@ -65,9 +118,38 @@ impl TaskOutput {
 
             TaskOutput::IOError(_) |
 			TaskOutput::TooManyThreadsError |
-			TaskOutput::PoisonError => true,
+			TaskOutput::PoisonError |
+			TaskOutput::TimedOut(_) |
+			TaskOutput::OutOfMemory(_) => true,
         }
 	}
+
+	/* The outcome of the process, if it was spawned at all */
+	pub fn outcome(&self) -> Option<&CommandOutcome> {
+		match self {
+		TaskOutput::NoError(x) |
+		TaskOutput::TimedOut(x) |
+		TaskOutput::OutOfMemory(x) => Some(x),
+
+		TaskOutput::Waiting |
+		TaskOutput::IOError(_) |
+		TaskOutput::TooManyThreadsError |
+		TaskOutput::PoisonError => None
+		}
+	}
+
+	pub fn outcome_mut(&mut self) -> Option<&mut CommandOutcome> {
+		match self {
+		TaskOutput::NoError(x) |
+		TaskOutput::TimedOut(x) |
+		TaskOutput::OutOfMemory(x) => Some(x),
+
+		TaskOutput::Waiting |
+		TaskOutput::IOError(_) |
+		TaskOutput::TooManyThreadsError |
+		TaskOutput::PoisonError => None
+		}
+	}
 }
 
 impl Try for TaskOutput {
@@ -84,7 +166,9 @@ impl Try for TaskOutput {
 		TaskOutput::Waiting |
 		TaskOutput::IOError(_) |
 		TaskOutput::TooManyThreadsError |
-		TaskOutput::PoisonError => ControlFlow::Break(self)
+		TaskOutput::PoisonError |
+		TaskOutput::TimedOut(_) |
+		TaskOutput::OutOfMemory(_) => ControlFlow::Break(self)
         }
     }
 }
@@ -105,14 +189,74 @@ impl<T> FromResidual<Result<Infallible, PoisonError<T>>> for TaskOutput {
     }
 }
 
+/* Secrets are only referred to by name, their value never appears in the
+ * configuration.
+ */
+#[derive(Deserialize, Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum EnvValue {
+	Plain(String),
+	Secret {
+		secret: String
+	}
+}
+
+/* How an inline script is given to its interpreter */
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptInput {
+	/* As the path of a private temporary file, before the args */
+	#[default]
+	File,
+	/* On the standard input */
+	Stdin
+}
+
+/* Data written to the stdin of the process */
+#[derive(Deserialize, Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stdin {
+	Text(String),
+	File(PathBuf),
+	/* The stdout of a task of the same group, by name or index. The task
+	 * only runs once the other one succeeded in the same group run.
+	 */
+	Task(String)
+}
+
+/* Either a program or an inline script must be given */
 #[derive(Deserialize, Clone, Debug, Serialize)]
 pub struct Command {
     #[serde(rename = "program")]
-	pub command: String,
+	pub command: Option<String>,
     #[serde(rename = "args")]
+	#[serde(default)]
 	pub arguments: Vec<String>,
+	#[serde(rename = "script")]
+	pub script: Option<String>,
+	/* Program and options running the script, /bin/sh -e by default */
+	#[serde(rename = "interpreter")]
+	pub interpreter: Option<Vec<String>>,
+	#[serde(rename = "script_input")]
+	#[serde(default)]
+	pub script_input: ScriptInput,
+	/* Prepends "set -o pipefail" to the script. The default interpreter is
+	 * then bash, since POSIX shells like dash don't have the option. */
+	#[serde(rename = "pipefail")]
+	#[serde(default)]
+	pub pipefail: bool,
     #[serde(rename = "envs")]
-	pub envs: Option<HashMap<String, String>>,
+	pub envs: Option<HashMap<String, EnvValue>>,
+	/* Starts from an empty environment rather than the server's one */
+	#[serde(rename = "env_clear")]
+	#[serde(default)]
+	pub env_clear: bool,
+	/* Starts from the listed variables of the server's environment only */
+	#[serde(rename = "env_inherit")]
+	pub env_inherit: Option<Vec<String>>,
+	#[serde(rename = "env_files")]
+	#[serde(default)]
+	pub env_files: Vec<PathBuf>,
     #[serde(rename = "chdir")]
 	#[serde(default = "default_path")]
 	pub current_dir: PathBuf,
@@ -120,38 +264,494 @@ pub struct Command {
 	pub uid: Option<u32>,
 	#[serde(rename = "gid")]
 	pub gid: Option<u32>,
+	#[serde(rename = "user")]
+	pub user: Option<String>,
+	#[serde(rename = "group")]
+	pub group: Option<String>,
+	#[serde(rename = "limits")]
+	pub limits: Option<Limits>,
+	#[serde(rename = "cgroup")]
+	pub cgroup: Option<CgroupSettings>,
+	/* In seconds */
+	#[serde(rename = "timeout")]
+	pub timeout: Option<u64>,
+	#[serde(rename = "sandbox")]
+	pub sandbox: Option<Sandbox>,
+	#[serde(rename = "stdin")]
+	pub stdin: Option<Stdin>,
+	#[serde(flatten)]
+	pub success: SuccessCriteria,
 }
 
 fn default_path() -> PathBuf {
 	std::env::current_dir().unwrap()
 }
 
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+	thread::spawn(move || {
+		let mut buffer = Vec::new();
+		if let Some(mut pipe) = pipe {
+			pipe.read_to_end(&mut buffer)?;
+		}
+		Ok(buffer)
+	})
+}
+
+/* The program of the interpreter, or what env runs, must be a shell with
+ * the pipefail option */
+fn supports_pipefail(interpreter: &[String]) -> bool {
+	let name = |x: &String| PathBuf::from(x)
+		.file_name()
+		.map(|x| x.to_string_lossy().into_owned())
+		.unwrap_or_default();
+
+	let mut args = interpreter.iter();
+	let mut program = args.next().map(name);
+	if program.as_deref() == Some("env") {
+		program = args.find(|x| !x.starts_with('-') && !x.contains('=')).map(name);
+	}
+	matches!(program.as_deref(), Some("bash" | "zsh" | "ksh"))
+}
+
+/* std::process::Child::wait() drops the rusage returned by the kernel, so
+ * the child is reaped by hand.
+ */
+fn wait4(pid: u32) -> io::Result<(ExitStatus, ResourceUsage)> {
+	let mut status = 0;
+	let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+	loop {
+		let ret = unsafe {
+			libc::wait4(pid as libc::pid_t, &mut status, 0, &mut usage)
+		};
+		if ret >= 0 {
+			break;
+		}
+
+		let err = io::Error::last_os_error();
+		if err.kind() != io::ErrorKind::Interrupted {
+			return Err(err);
+		}
+	}
+
+	Ok((ExitStatus::from_raw(status), ResourceUsage::from(&usage)))
+}
+
+/* Kills the execution if it is still running after the timeout. Returns
+ * whether it had to.
+ */
+fn watchdog(timeout: Duration, pid: u32, cgroup: Option<PathBuf>) -> (mpsc::Sender<()>, thread::JoinHandle<bool>) {
+	let (tx, rx) = mpsc::channel();
+	let handler = thread::spawn(move || {
+		if rx.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+			return false;
+		}
+
+		warn!("Execution {} timed out, killing it", pid);
+		let killed_cgroup = cgroup.as_deref()
+			.map(cgroup::kill)
+			.is_some_and(|x| x.is_ok());
+		if !killed_cgroup {
+			unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+		}
+		true
+	});
+
+	(tx, handler)
+}
+
+fn not_found(what: &str, name: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::NotFound, format!("Unknown {}: {}", what, name))
+}
+
+fn render(value: &str, vars: &Variables) -> io::Result<String> {
+	template::render(value, vars)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
 impl Command {
-	pub fn run(&self) -> TaskOutput {
-		let start = Instant::now();
-		let mut cmd = std::process::Command::new(&self.command);
+	/* Uid the execution runs as */
+	pub fn uid(&self) -> io::Result<u32> {
+		Ok(self.credentials()?.0.uid.unwrap_or_else(|| unsafe { libc::geteuid() }))
+	}
+
+	/* Resolves the user and group names through NSS. Numeric ids without a
+	 * matching user database entry are used as is.
+	 */
+	fn credentials(&self) -> io::Result<(Credentials, Option<User>)> {
+		let user = match (&self.user, self.uid) {
+		(Some(name), _) => Some(
+			User::from_name(name)?.ok_or_else(|| not_found("user", name))?
+		),
+		(None, Some(uid)) => User::from_uid(uid)?,
+		(None, None) => None
+		};
+
+		let gid = match &self.group {
+		Some(name) => Some(
+			user::group_id(name)?.ok_or_else(|| not_found("group", name))?
+		),
+		None => self.gid.or(user.as_ref().map(|x| x.gid))
+		};
+
+		let groups = match &user {
+		Some(user) => Some(user.groups(gid.unwrap_or(user.gid))?),
+		None => None
+		};
 
-		cmd.args(&self.arguments);
+		let credentials = Credentials {
+			uid: user.as_ref().map(|x| x.uid).or(self.uid),
+			gid,
+			groups
+		};
+		Ok((credentials, user))
+	}
+
+	/* Values in which {{placeholders}} are replaced */
+	fn templates(&self) -> impl Iterator<Item = &str> {
+		self.command.as_deref().into_iter()
+			.chain(self.script.as_deref())
+			.chain(match &self.stdin {
+				Some(Stdin::Text(x)) => Some(x.as_str()),
+				_ => None
+			})
+			.chain(self.arguments.iter().map(|x| x.as_str()))
+			.chain(self.current_dir.to_str())
+			.chain(self.envs.iter().flat_map(|x| x.values().filter_map(|x| match x {
+				EnvValue::Plain(x) => Some(x.as_str()),
+				EnvValue::Secret { .. } => None
+			})))
+	}
+
+	/* The program to run and its first arguments, followed by the user's
+	 * args, with the script to write if any.
+	 */
+	fn invocation(&self, vars: &Variables) -> io::Result<(String, Vec<String>, Option<Script>)> {
+		let script = match &self.script {
+			Some(body) => body,
+			None => {
+				let program = self.command.as_ref()
+					.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Missing program"))?;
+				return Ok((render(program, vars)?, Vec::new(), None));
+			}
+		};
+
+		let shell = if self.pipefail { "/bin/bash" } else { "/bin/sh" };
+		let mut interpreter = self.interpreter.clone()
+			.unwrap_or_else(|| match self.script_input {
+			ScriptInput::File => vec![String::from(shell), String::from("-e")],
+			ScriptInput::Stdin => vec![String::from(shell), String::from("-e"), String::from("-s")]
+			})
+			.into_iter();
+		let program = interpreter.next()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty interpreter"))?;
+
+		let mut body = String::new();
+		if self.pipefail {
+			body.push_str("set -o pipefail\n");
+		}
+		body.push_str(&render(script, vars)?);
+
+		Ok((program, interpreter.collect(), Some(Script::new(body.into_bytes()))))
+	}
+
+	/* Checks done when a command is submitted, rather than when it runs */
+	pub fn validate(&self) -> Result<(), String> {
+		match (&self.command, &self.script) {
+		(Some(_), Some(_)) => return Err(String::from("Both a program and a script are given")),
+		(None, None) => return Err(String::from("Either a program or a script must be given")),
+		(Some(_), None) if self.interpreter.is_some() =>
+			return Err(String::from("An interpreter is only used with a script")),
+		_ => {}
+		}
+		if self.interpreter.as_ref().is_some_and(|x| x.is_empty()) {
+			return Err(String::from("Empty interpreter"));
+		}
+		if self.pipefail && !self.interpreter.as_ref().is_none_or(|x| supports_pipefail(x)) {
+			return Err(String::from("pipefail needs bash, zsh or ksh as the interpreter"));
+		}
+		if self.stdin.is_some() && self.script.is_some() && self.script_input == ScriptInput::Stdin {
+			return Err(String::from("The stdin is already used by the script"));
+		}
+		/* The temporary file would be hidden by the private /tmp */
+		if self.script.is_some()
+			&& self.script_input == ScriptInput::File
+			&& self.sandbox.as_ref().is_some_and(|x| x.private_tmp) {
+			return Err(String::from("Scripts must be given on stdin with a private /tmp"));
+		}
+
+		if let (Some(name), Some(uid)) = (&self.user, self.uid) {
+			return Err(format!("Both user \"{}\" and uid {} are given", name, uid));
+		}
+		if let (Some(name), Some(gid)) = (&self.group, self.gid) {
+			return Err(format!("Both group \"{}\" and gid {} are given", name, gid));
+		}
+
+		/* The outputs of the other tasks are only known when running */
+		let mut vars = RunContext::default().template_variables();
+		vars.insert(format!("{}*", env::OUTPUT_TEMPLATE_PREFIX), template::Value::Text(String::new()));
+		for value in self.templates() {
+			template::validate(value, &vars)?;
+		}
+
+		self.success.validate()?;
+		if let Some(limits) = &self.limits {
+			limits.validate()?;
+		}
+		let uid = self.uid().map_err(|e| e.to_string())?;
+		if let Some(sandbox) = &self.sandbox {
+			sandbox.prepare().map_err(|e| e.to_string())?;
+			/* The filter is installed once the privileges are dropped */
+			if !sandbox.seccomp_deny.is_empty() && !sandbox.no_new_privs && uid != 0 {
+				return Err(String::from("seccomp_deny needs no_new_privs when not running as root"));
+			}
+		}
+
+		Ok(())
+	}
+
+	/* Builds the environment of the execution, by order of precedence:
+	 * the explicit envs, the env_files, the scheduler variables, the login
+	 * variables and what is inherited from the server. The values of envs
+	 * and env_files may refer to the variables defined before them.
+	 * Also returns the values of the secrets, to mask them in the logs.
+	 */
+	fn environment(&self, ctx: &RunContext, user: Option<&User>) -> io::Result<(HashMap<String, String>, Vec<String>)> {
+		let mut vars: HashMap<String, String> =
+			if self.env_clear {
+				HashMap::new()
+			} else {
+				std::env::vars_os()
+					.filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+					.filter(|(k, _)| self.env_inherit.as_ref()
+						.is_none_or(|allowed| allowed.contains(k)))
+					.collect()
+			};
+
+		if let Some(user) = user {
+			for (k, v) in user.login_environment() {
+				vars.insert(k.to_string(), v.to_string_lossy().into_owned());
+			}
+		}
+
+		for (k, v) in ctx.variables() {
+			vars.insert(k, v);
+		}
+
+		for path in self.env_files.iter() {
+			let content = std::fs::read_to_string(path)?;
+			let parsed = env::parse_dotenv(&content)
+				.map_err(|e| io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("{}: {}", path.display(), e)
+				))?;
+			for (k, v) in parsed {
+				let v = env::interpolate(&v, &vars);
+				vars.insert(k, v);
+			}
+		}
+
+		let mut secret_values = Vec::new();
 		if let Some(map) = &self.envs {
-			cmd.envs(map);
+			/* Sorted, so that the result doesn't depend on the hash order */
+			let mut envs: Vec<_> = map.iter().collect();
+			envs.sort_by_key(|(k, _)| *k);
+			let templates = ctx.template_variables();
+			for (k, v) in envs {
+				let v = match v {
+				EnvValue::Plain(v) => env::interpolate(&render(v, &templates)?, &vars),
+				EnvValue::Secret { secret } => {
+					let value = ctx.secrets.as_ref()
+						.ok_or_else(|| not_found("secret", secret))?
+						.resolve(secret)?;
+					secret_values.push(value.clone());
+					value
+				}
+				};
+				vars.insert(k.clone(), v);
+			}
 		}
-		cmd.current_dir(&self.current_dir);
-		if let Some(id) = self.uid {
-			cmd.uid(id);
+
+		Ok((vars, secret_values))
+	}
+
+	/* When the context holds a cgroup path, the execution runs inside a
+	 * dedicated cgroup created there, and removed afterward.
+	 */
+	pub fn run(&self, ctx: &RunContext) -> TaskOutput {
+		self.run_with_input(ctx, None)
+	}
+
+	fn stdin(&self, ctx: &RunContext, vars: &Variables) -> io::Result<Option<Vec<u8>>> {
+		Ok(match &self.stdin {
+		None => None,
+		Some(Stdin::Text(x)) => Some(render(x, vars)?.into_bytes()),
+		Some(Stdin::File(path)) => Some(std::fs::read(path)?),
+		Some(Stdin::Task(name)) => Some(
+			ctx.input.clone().ok_or_else(|| not_found("output of task", name))?
+		)
+		})
+	}
+
+	/* Same as run, with the given data written to the stdin of the process */
+	pub fn run_with_input(&self, ctx: &RunContext, input: Option<Vec<u8>>) -> TaskOutput {
+		let start = Instant::now();
+		let vars = ctx.template_variables();
+		let mut input = match (input, self.stdin(ctx, &vars)?) {
+			(Some(_), Some(_)) => return TaskOutput::IOError(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"The stdin of the command is already set"
+			)),
+			(input, stdin) => input.or(stdin)
+		};
+		let (program, args, script) = self.invocation(&vars)?;
+		let mut cmd = std::process::Command::new(program);
+		cmd.args(args);
+
+		let (credentials, user) = self.credentials()?;
+
+		/* Removed when dropped, once the execution is over */
+		let mut script_file = None;
+		let script_hash = script.as_ref().map(|x| x.hash.clone());
+		if let Some(script) = script {
+			match self.script_input {
+			ScriptInput::File => {
+				let file = script.write(&credentials)?;
+				cmd.arg(file.path());
+				script_file = Some(file);
+			},
+			ScriptInput::Stdin => {
+				if input.is_some() {
+					return TaskOutput::IOError(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"The stdin is already used by the script"
+					));
+				}
+				input = Some(script.body);
+			}
+			}
 		}
-		if let Some(id) = self.gid {
-			cmd.gid(id);
+
+		for arg in self.arguments.iter() {
+			cmd.arg(render(arg, &vars)?);
 		}
+		let (envs, secret_values) = self.environment(ctx, user.as_ref())?;
+		cmd.env_clear();
+		cmd.envs(envs);
+		match self.current_dir.to_str() {
+		Some(path) => cmd.current_dir(render(path, &vars)?),
+		None => cmd.current_dir(&self.current_dir)
+		};
 
-		let output = cmd.output()?;
+		let sandbox = match &self.sandbox {
+			Some(sandbox) => Some(Arc::new(sandbox.prepare()?)),
+			None => None
+		};
+		let cgroup = match &ctx.cgroup {
+			Some(path) => Some(Cgroup::create(path, self.cgroup.as_ref())?),
+			None => None
+		};
+		if let Some(cgroup) = &cgroup {
+			let fd = cgroup.procs_fd();
+			unsafe {
+				cmd.pre_exec(move || cgroup::join(fd));
+			}
+		}
+		if let Some(limits) = self.limits.clone() {
+			unsafe {
+				cmd.pre_exec(move || limits.apply());
+			}
+		}
+		/* The hooks run in order: enter the sandbox while still privileged,
+		 * then drop the privileges, and lock everything down at last.
+		 */
+		if let Some(sandbox) = sandbox.clone() {
+			unsafe {
+				cmd.pre_exec(move || sandbox.enter());
+			}
+		}
+		unsafe {
+			cmd.pre_exec(move || credentials.apply());
+		}
+		if let Some(sandbox) = sandbox {
+			unsafe {
+				cmd.pre_exec(move || sandbox.restrict());
+			}
+		}
+		/* Without a cgroup, the process group is the best handle on the
+		 * whole tree of processes */
+		cmd.process_group(0);
+		cmd.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped());
+
+		let mut child = match cmd.spawn() {
+			Ok(child) => child,
+			Err(e) => {
+				if let Some(cgroup) = cgroup {
+					cgroup.remove();
+				}
+				return TaskOutput::IOError(e);
+			}
+		};
+		let stdout = read_pipe(child.stdout.take());
+		let stderr = read_pipe(child.stderr.take());
+		if let (Some(mut pipe), Some(input)) = (child.stdin.take(), input) {
+			/* The process may not read all of it, so errors are ignored */
+			thread::spawn(move || pipe.write_all(&input));
+		}
+
+		let watchdog = self.timeout.map(|timeout| watchdog(
+			Duration::from_secs(timeout),
+			child.id(),
+			cgroup.as_ref().map(|x| x.kill_path())
+		));
+
+		let waited = wait4(child.id());
 		let duration = start.elapsed();
+		drop(script_file);
 
-		TaskOutput::NoError(CommandOutcome {
-			exit_status: output.status,
-			stdout: Log::from_vec(output.stdout),
-			stderr: Log::from_vec(output.stderr),
-			start: start,
-			duration: duration
-		})
+		let timed_out = watchdog.is_some_and(|(tx, handler)| {
+			drop(tx);
+			handler.join().unwrap()
+		});
+
+		let mut memory_peak = None;
+		let mut out_of_memory = false;
+		if let Some(cgroup) = cgroup {
+			memory_peak = cgroup.memory_peak();
+			out_of_memory = cgroup.oom_killed();
+			cgroup.remove();
+		}
+
+		let (exit_status, mut usage) = waited?;
+		usage.memory_peak = memory_peak;
+
+		let stdout = secrets::mask(stdout.join().unwrap()?, &secret_values);
+		let stderr = secrets::mask(stderr.join().unwrap()?, &secret_values);
+		let success = self.success.is_success(&exit_status, &stdout, &stderr)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+		let outcome = CommandOutcome {
+			exit_status,
+			stdout: Log::from_vec(stdout),
+			stderr: Log::from_vec(stderr),
+			start,
+			duration,
+			usage,
+			success,
+			script_hash,
+			outputs: BTreeMap::new(),
+			scheduled_time: ctx.scheduled_time,
+			fire_time: ctx.fire_time
+		};
+
+		if timed_out {
+			TaskOutput::TimedOut(outcome)
+		} else if out_of_memory {
+			TaskOutput::OutOfMemory(outcome)
+		} else {
+			TaskOutput::NoError(outcome)
+		}
 	}
 }
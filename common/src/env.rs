@@ -0,0 +1,181 @@
+use std::{collections::{BTreeMap, HashMap}, fs::OpenOptions, io::{self, Read}, os::unix::fs::{MetadataExt, OpenOptionsExt}, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Utc};
+
+use crate::{secrets::SecretStore, template::{Value, Variables}};
+
+/* What a command needs to know about the execution it is part of */
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    pub group: String,
+    pub task: String,
+    pub run_id: u64,
+    pub scheduled_time: DateTime<Utc>,
+    /* When the task was actually started, after the jitter, the spread,
+     * or a deferral */
+    pub fire_time: DateTime<Utc>,
+    pub attempt: u32,
+    pub prev_success_time: Option<DateTime<Utc>>,
+
+    pub cgroup: Option<PathBuf>,
+    pub secrets: Option<SecretStore>,
+    /* Stdout of the task this one reads its stdin from */
+    pub input: Option<Vec<u8>>,
+    /* Scratch directory shared by the tasks of the group run */
+    pub run_dir: Option<PathBuf>,
+    /* Where the execution may write its key=value outputs */
+    pub outputs_file: Option<PathBuf>,
+    /* Outputs of the tasks of the group run that are over */
+    pub outputs: BTreeMap<String, String>,
+    /* File whose event started the group run */
+    pub trigger_path: Option<PathBuf>,
+}
+
+/* Prefix of the outputs, in the templates and the environment */
+pub const OUTPUT_TEMPLATE_PREFIX: &str = "output.";
+pub const OUTPUT_ENV_PREFIX: &str = "SCHEDULER_OUTPUT_";
+
+impl RunContext {
+    /* Variables injected in the environment of every execution */
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let mut out: Vec<(String, String)> = [
+            ("SCHEDULER_GROUP", self.group.clone()),
+            ("SCHEDULER_TASK", self.task.clone()),
+            ("SCHEDULER_RUN_ID", self.run_id.to_string()),
+            ("SCHEDULER_SCHEDULED_TIME", self.scheduled_time.to_rfc3339()),
+            ("SCHEDULER_FIRE_TIME", self.fire_time.to_rfc3339()),
+            ("SCHEDULER_ATTEMPT", self.attempt.to_string()),
+        ].into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+
+        let paths = [
+            ("SCHEDULER_RUN_DIR", &self.run_dir),
+            ("SCHEDULER_OUTPUTS", &self.outputs_file),
+            ("SCHEDULER_TRIGGER_PATH", &self.trigger_path),
+        ];
+        for (k, path) in paths {
+            if let Some(path) = path {
+                out.push((k.to_string(), path.to_string_lossy().into_owned()));
+            }
+        }
+
+        for (k, v) in self.outputs.iter() {
+            out.push((format!("{}{}", OUTPUT_ENV_PREFIX, k), v.clone()));
+        }
+        out
+    }
+
+    /* Values of the {{placeholders}} of the commands */
+    pub fn template_variables(&self) -> Variables {
+        [
+            ("group", Value::Text(self.group.clone())),
+            ("task", Value::Text(self.task.clone())),
+            ("run_id", Value::Text(self.run_id.to_string())),
+            ("attempt", Value::Text(self.attempt.to_string())),
+            ("scheduled_time", Value::Time(Some(self.scheduled_time))),
+            ("fire_time", Value::Time(Some(self.fire_time))),
+            ("prev_success_time", Value::Time(self.prev_success_time)),
+            ("trigger_path", Value::Text(self.trigger_path.as_ref()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default())),
+        ].into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .chain(self.outputs.iter().map(|(k, v)|
+                (format!("{}{}", OUTPUT_TEMPLATE_PREFIX, k), Value::Text(v.clone()))
+            ))
+            .collect()
+    }
+}
+
+/* Replaces the ${VAR} references with the value of VAR, or nothing if it
+ * isn't set. "$$" stands for a lone "$".
+ */
+pub fn interpolate(value: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[.. idx]);
+        rest = &rest[idx ..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${")
+            && let Some(end) = after.find('}') {
+            if let Some(x) = vars.get(&after[.. end]) {
+                out.push_str(x);
+            }
+            rest = &after[end + 1 ..];
+        } else {
+            out.push('$');
+            rest = &rest[1 ..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/* Reads the key=value outputs written by an execution. The file must be
+ * a regular file of the user the execution ran as: it lives in a directory
+ * writable by every task, and is read by the server.
+ */
+pub fn read_outputs(path: &Path, owner: u32) -> Result<BTreeMap<String, String>, String> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path);
+    let mut file = match file {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+    Err(e) => return Err(e.to_string())
+    };
+
+    let metadata = file.metadata().map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err(String::from("Not a regular file"));
+    }
+    if metadata.uid() != owner {
+        return Err(format!("Owned by uid {} instead of {}", metadata.uid(), owner));
+    }
+
+    let mut content = String::new();
+    file.read_to_string(&mut content).map_err(|e| e.to_string())?;
+    Ok(parse_dotenv(&content)?.into_iter().collect())
+}
+
+/* Parses a dotenv file: KEY=VALUE lines, with an optional "export "
+ * prefix, optionally quoted values, and # comments.
+ */
+pub fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut out = Vec::new();
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| format!("Line {}: missing '='", n + 1))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Line {}: invalid name \"{}\"", n + 1, key));
+        }
+
+        let value = value.trim();
+        let value =
+            match value.chars().next() {
+            Some(q @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(q) =>
+                &value[1 .. value.len() - 1],
+            _ => value.split(" #").next().unwrap().trim_end()
+            };
+
+        out.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(out)
+}
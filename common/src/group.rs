@@ -1,10 +1,88 @@
-use std::path::PathBuf;
+use std::{collections::{BTreeMap, HashMap}, ffi::{CString, OsString}, fmt, fs::{self, DirBuilder}, hash::{BuildHasher, RandomState}, io, os::unix::{ffi::{OsStrExt, OsStringExt}, fs::{DirBuilderExt, PermissionsExt}}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, OnceLock}};
 
-use log::{debug, info};
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use log::{debug, error, info, warn};
+use chrono::{DateTime, Duration, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 
-use crate::{task::{Task, TaskConfig}, utils::{get_period_from_string, get_start_timestamp_from_string, YmdHmsDuration}};
+use crate::{calendar::{BlackoutPolicy, CalendarConstraints, Calendars}, cgroup, command::Stdin, env::RunContext, hooks::{self, Hooks}, notify::SmtpRelay, secrets::SecretStore, task::{Task, TaskConfig}, watch::{FileTrigger, FileWatcher}, utils::{get_period_from_string, get_start_timestamp_from_string, YmdHmsDuration}};
+
+/* A group run whose tasks are not all over */
+#[derive(Debug, Default)]
+struct GroupRun {
+    dir: Option<PathBuf>,
+    outputs: BTreeMap<String, String>,
+    /* Tasks that are over, with their execution if they succeeded */
+    finished: HashMap<usize, Option<usize>>,
+}
+
+/* The directories holding the run directories can be crossed by the
+ * tasks, which may run as other users, but neither listed nor written */
+const RUNS_MODE: u32 = 0o711;
+
+/* Tells apart the groups keeping their runs in the private directory */
+static GROUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/* Where the groups without a log directory keep their runs: a directory
+ * of the server, whose name can't be guessed, created once */
+fn private_runs_root() -> Option<&'static Path> {
+    static ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let template = std::env::temp_dir().join("scheduler-runs-XXXXXX");
+        let mut template = CString::new(template.as_os_str().as_bytes())
+            .ok()?
+            .into_bytes_with_nul();
+        if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+            error!("Unable to create the directory of the runs: {}", io::Error::last_os_error());
+            return None;
+        }
+        template.pop();
+
+        let path = PathBuf::from(OsString::from_vec(template));
+        if let Err(e) = fs::set_permissions(&path, fs::Permissions::from_mode(RUNS_MODE)) {
+            error!("Unable to set the permissions of {:?}: {}", path, e);
+            return None;
+        }
+        Some(path)
+    }).as_deref()
+}
+
+/* What becomes of a group once it has no occurrence left */
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpireAction {
+    /* Kept, but not scheduled anymore */
+    #[default]
+    Disable,
+    /* Removed from the environment once its tasks are over */
+    Remove,
+}
+
+/* Where a group was declared. The server only saves the groups of its
+ * configuration file, and the ones it was given in a section of their own,
+ * never the included files which belong to the operators */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Provenance {
+    #[default]
+    Config,
+    /* Submitted to the server, through the network or the spool */
+    Api,
+    Include(PathBuf),
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+        Provenance::Config => write!(f, "the configuration"),
+        Provenance::Api => write!(f, "a submission"),
+        Provenance::Include(path) => write!(f, "{}", path.display())
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(x: &T) -> bool {
+    *x == T::default()
+}
 
 #[derive(Debug)]
 pub struct TaskGroup {
@@ -13,9 +91,43 @@ pub struct TaskGroup {
     starts_at_str: Option<String>,
     period: Option<YmdHmsDuration>,
     period_str: Option<String>,
+    ends_at: Option<DateTime<Utc>>,
+    ends_at_str: Option<String>,
+    max_runs: Option<u64>,
+    expire_action: ExpireAction,
+    /* Seconds */
+    jitter: Option<u64>,
+    deterministic_jitter: bool,
+    spread: Option<u64>,
+    on_file: Option<FileTrigger>,
+    watcher: Option<FileWatcher>,
     processes: Vec<Task>,
+    hooks: Hooks,
+    smtp: Option<SmtpRelay>,
+    constraints: CalendarConstraints,
+    calendars: Arc<Calendars>,
+    provenance: Provenance,
 
-    next_execution: Option<DateTime<Utc>>
+    next_execution: Option<DateTime<Utc>>,
+    /* Occurrence of the schedule next_execution was computed from,
+     * before the jitter */
+    next_nominal: Option<DateTime<Utc>>,
+    /* Nominal time of the occurrence next_execution was deferred from */
+    deferred_from: Option<DateTime<Utc>>,
+    runs: u64,
+    expired: bool,
+    /* Whether the persisted state changed since last saved */
+    dirty: bool,
+    /* Tasks waiting for others of the same run */
+    waiting: Vec<(usize, RunContext)>,
+    /* Tasks whose start is spread, with when to start them */
+    delayed: Vec<(DateTime<Utc>, usize, RunContext)>,
+    /* Runs for files, with when to launch them, and when the file was
+     * ready */
+    file_runs: Vec<(DateTime<Utc>, DateTime<Utc>, PathBuf)>,
+    active_runs: HashMap<u64, GroupRun>,
+    /* Where the scratch directories of the runs are created */
+    runs_path: Option<PathBuf>
 }
 
 #[derive(Deserialize, Serialize)]
@@ -23,15 +135,172 @@ pub struct SerializedTaskGroup {
     name: String,
     starts_at: Option<String>,
     period: Option<String>,
-    processes: Vec<TaskConfig>
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ends_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_runs: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    expire_action: ExpireAction,
+    /* Random delay of each occurrence, up to this many seconds */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jitter: Option<u64>,
+    /* The delay is derived from the name of the group instead, and is
+     * the same for every occurrence */
+    #[serde(default, skip_serializing_if = "is_default")]
+    deterministic_jitter: bool,
+    /* The tasks of an occurrence are evenly started over this many
+     * seconds */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spread: Option<u64>,
+    /* Runs the group for files, with or without a period */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_file: Option<FileTrigger>,
+    processes: Vec<TaskConfig>,
+    #[serde(flatten)]
+    hooks: Hooks,
+    #[serde(flatten)]
+    constraints: CalendarConstraints,
+    #[serde(flatten)]
+    state: GroupState
+}
+
+/* State of a group, kept across restarts */
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct GroupState {
+    #[serde(default, skip_serializing_if = "is_default")]
+    runs: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    expired: bool,
+    /* Nominal time of the occurrence deferred by the calendars, and when
+     * it runs instead */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deferred_from: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deferred_to: Option<DateTime<Utc>>
+}
+
+impl SerializedTaskGroup {
+    /* Runs the task once, then goes away */
+    pub fn one_shot(name: String, time: DateTime<Utc>, jitter: Option<u64>, task: TaskConfig) -> Self {
+        Self {
+            name,
+            starts_at: Some(time.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            period: None,
+            ends_at: None,
+            max_runs: Some(1),
+            expire_action: ExpireAction::Remove,
+            jitter,
+            deterministic_jitter: false,
+            spread: None,
+            on_file: None,
+            processes: vec![task],
+            hooks: Hooks::default(),
+            constraints: CalendarConstraints::default(),
+            state: GroupState::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn calendars(&self) -> impl Iterator<Item = &str> {
+        self.constraints.names()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(x) = &self.starts_at
+            && get_start_timestamp_from_string(x).is_none() {
+            return Err(format!("Invalid date: {}", x));
+        }
+
+        if let Some(x) = &self.period
+            && get_period_from_string(x).is_none() {
+            return Err(format!("Invalid period: {}", x));
+        }
+
+        if let Some(x) = &self.ends_at
+            && get_start_timestamp_from_string(x).is_none() {
+            return Err(format!("Invalid date: {}", x));
+        }
+
+        if let Some(trigger) = &self.on_file {
+            trigger.validate()
+                .map_err(|e| format!("\"{}\": {}", self.name, e))?;
+        }
+
+        if self.max_runs == Some(0) {
+            return Err(format!("\"{}\": max_runs must be positive", self.name));
+        }
+
+        self.hooks.validate()
+            .map_err(|e| format!("\"{}\": {}", self.name, e))?;
+
+        let labels: Vec<String> = self.processes.iter()
+            .enumerate()
+            .map(|(id, conf)| conf.name.clone().unwrap_or(id.to_string()))
+            .collect();
+        /* The dependencies and the metrics tell the tasks apart by label */
+        for (id, label) in labels.iter().enumerate() {
+            if labels[.. id].contains(label) {
+                return Err(format!("\"{}\", task {}: Another task is labelled \"{}\"", self.name, id, label));
+            }
+        }
+        let mut dependencies = Vec::with_capacity(self.processes.len());
+
+        for (id, conf) in self.processes.iter().enumerate() {
+            conf.cmd.validate()
+                .and_then(|_| conf.hooks.validate())
+                .and_then(|_| conf.run_when.as_ref().map_or(Ok(()), |x| x.validate()))
+                .map_err(|e| format!("\"{}\", task {}: {}", self.name, id, e))?;
+
+            let names = conf.depends_on.iter()
+                .chain(match &conf.cmd.stdin {
+                    Some(Stdin::Task(name)) => Some(name),
+                    _ => None
+                });
+            let mut ids = Vec::new();
+            for name in names {
+                let dep = labels.iter().position(|x| x == name)
+                    .ok_or_else(|| format!("\"{}\", task {}: Unknown task \"{}\"", self.name, id, name))?;
+                ids.push(dep);
+            }
+            dependencies.push(ids);
+        }
+
+        /* Depth-first search, a task still being visited when reached
+         * again is part of a cycle */
+        fn visit(id: usize, dependencies: &[Vec<usize>], state: &mut [u8]) -> bool {
+            match state[id] {
+            1 => return false,
+            2 => return true,
+            _ => {}
+            }
+            state[id] = 1;
+            if !dependencies[id].iter().all(|x| visit(*x, dependencies, state)) {
+                return false;
+            }
+            state[id] = 2;
+            true
+        }
+
+        let mut state = vec![0; self.processes.len()];
+        for id in 0 .. self.processes.len() {
+            if !visit(id, &dependencies, &mut state) {
+                return Err(format!("\"{}\", task {}: Cycle in the dependencies of the tasks", self.name, id));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'de> Deserialize<'de> for TaskGroup {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where D: Deserializer<'de> {
-        Ok(TaskGroup::from(
-            SerializedTaskGroup::deserialize(deserializer)?
-        ))
+        let conf = SerializedTaskGroup::deserialize(deserializer)?;
+        conf.validate().map_err(de::Error::custom)?;
+        Ok(TaskGroup::from(conf))
     }
 }
 
@@ -42,16 +311,26 @@ impl Serialize for TaskGroup {
             name: self.name.clone(),
             starts_at: self.starts_at_str.clone(),
             period: self.period_str.clone(),
+            ends_at: self.ends_at_str.clone(),
+            max_runs: self.max_runs,
+            expire_action: self.expire_action,
+            jitter: self.jitter,
+            deterministic_jitter: self.deterministic_jitter,
+            spread: self.spread,
+            on_file: self.on_file.clone(),
             processes: self.processes.iter()
                 .map(|task| task.config())
-                .collect()
+                .collect(),
+            hooks: self.hooks.clone(),
+            constraints: self.constraints.clone(),
+            state: self.state()
         }.serialize(serializer)
     }
 }
 
 impl From<SerializedTaskGroup> for TaskGroup {
     fn from(conf: SerializedTaskGroup) -> Self {
-        TaskGroup::new(
+        let mut out = TaskGroup::new(
             conf.name,
             conf.starts_at,
             conf.period,
@@ -60,7 +339,31 @@ impl From<SerializedTaskGroup> for TaskGroup {
                     Task::new(conf.clone())
                 })
                 .collect()
-        )
+        );
+        out.hooks = conf.hooks;
+        out.constraints = conf.constraints;
+        out.ends_at = conf.ends_at.as_ref()
+            .map(|x|
+                get_start_timestamp_from_string(x.as_str())
+                    .unwrap_or_else(|| panic!("Invalid date: {}", x))
+            );
+        out.ends_at_str = conf.ends_at;
+        out.max_runs = conf.max_runs;
+        out.expire_action = conf.expire_action;
+        out.jitter = conf.jitter;
+        out.deterministic_jitter = conf.deterministic_jitter;
+        out.spread = conf.spread;
+        if let Some(trigger) = conf.on_file {
+            match FileWatcher::new(trigger.clone()) {
+            Ok(watcher) => out.watcher = Some(watcher),
+            Err(e) => error!("\"{}\": Unable to watch {:?}: {}", out.name, trigger.path, e)
+            }
+            out.on_file = Some(trigger);
+        }
+        /* Computed again, with the jitter */
+        out.update_next_execution(None);
+        out.restore_state(conf.state);
+        out
     }
 }
 
@@ -74,30 +377,52 @@ impl TaskGroup {
         let starts_at_date = starts_at.as_ref()
             .map(|x|
                 get_start_timestamp_from_string(x.as_str())
-                    .expect(format!("Invalid date: {}", x).as_str())
+                    .unwrap_or_else(|| panic!("Invalid date: {}", x))
             );
 
         let period_ymd_hms = period.as_ref()
             .map(|x|
                 get_period_from_string(x.as_str())
-                    .expect(format!("Invalid period: {}", x).as_str())
+                    .unwrap_or_else(|| panic!("Invalid period: {}", x))
             );
 
         let mut out = Self {
-            name: name,
+            name: name.clone(),
             starts_at_str: starts_at,
             starts_at: starts_at_date,
             period_str: period,
             period: period_ymd_hms,
-            processes: processes,
+            ends_at: None,
+            ends_at_str: None,
+            max_runs: None,
+            expire_action: ExpireAction::Disable,
+            jitter: None,
+            deterministic_jitter: false,
+            spread: None,
+            on_file: None,
+            watcher: None,
+            processes,
+            hooks: Hooks::default(),
+            smtp: None,
+            constraints: CalendarConstraints::default(),
+            calendars: Arc::new(Calendars::new()),
+            provenance: Provenance::Config,
 
-            next_execution: None
+            next_execution: None,
+            next_nominal: None,
+            deferred_from: None,
+            runs: 0,
+            expired: false,
+            dirty: false,
+            waiting: Vec::new(),
+            delayed: Vec::new(),
+            file_runs: Vec::new(),
+            active_runs: HashMap::new(),
+            runs_path: private_runs_root()
+                .map(|path| path.join(GROUP_COUNTER.fetch_add(1, Ordering::Relaxed).to_string()))
         };
 
-        if let Some(start) = out.starts_at {
-            out.update_next_execution(start);
-        }
-
+        out.update_next_execution(None);
         out
     }
 
@@ -110,24 +435,257 @@ impl TaskGroup {
             let task_path = path.join(id.to_string());
             task.set_log_path(task_path);
         }
+        self.runs_path = Some(path.join("runs"));
+    }
+
+    pub fn set_cgroup_path(&mut self, path: PathBuf) {
+        if let Err(e) = cgroup::create_parent(&path) {
+            error!("\"{}\": Unable to set up the cgroup {:?}: {}", self.name, path, e);
+            return;
+        }
+
+        for (id, task) in self.processes.iter_mut().enumerate() {
+            task.set_cgroup_path(path.join(id.to_string()));
+        }
+    }
+
+    pub fn set_smtp_relay(&mut self, relay: SmtpRelay) {
+        self.smtp = Some(relay);
+    }
+
+    /* Names of the calendars the group refers to */
+    pub fn calendar_names(&self) -> impl Iterator<Item = &str> {
+        self.constraints.names()
+    }
+
+    pub fn set_calendars(&mut self, calendars: Arc<Calendars>) {
+        self.calendars = calendars;
+    }
+
+    pub fn set_secret_store(&mut self, store: SecretStore) {
+        for task in self.processes.iter_mut() {
+            task.set_secret_store(store.clone());
+        }
     }
 
-    fn update_next_execution(&mut self, last_execution: DateTime<Utc>) {
+    fn jitter(&self, nominal: DateTime<Utc>) -> Duration {
+        let Some(max) = self.jitter.filter(|x| *x > 0) else {
+            return Duration::zero();
+        };
+
+        let value = if self.deterministic_jitter {
+            let hash = Sha256::digest(self.name.as_bytes());
+            u64::from_be_bytes(hash[.. 8].try_into().unwrap())
+        } else {
+            RandomState::new().hash_one(nominal)
+        };
+        Duration::seconds((value % (max + 1)) as i64)
+    }
+
+    /* Occurrences are computed from starts_at, the first one not in the
+     * past and after the given one */
+    fn update_next_execution(&mut self, last_execution: Option<DateTime<Utc>>) {
         let now = Utc::now();
-        match &self.period {
-        None => self.next_execution =
-            if last_execution > now {
-                Some(last_execution)
+        let from = match last_execution {
+        Some(last) => (last + Duration::nanoseconds(1)).max(now),
+        None => now
+        };
+
+        self.next_nominal = match (&self.period, self.starts_at) {
+        (_, None) => None,
+        (None, Some(start)) => (start > from).then_some(start),
+        (Some(period), Some(start)) => {
+            let next = period.occurrence_from(start, from);
+            if next.is_none() {
+                error!("\"{}\": No occurrence can be computed after {}", self.name, from);
+            }
+            next
+        }
+        };
+        self.next_execution = self.next_nominal
+            .map(|x| x + self.jitter(x));
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    pub fn set_provenance(&mut self, provenance: Provenance) {
+        self.provenance = provenance;
+    }
+
+    pub fn state(&self) -> GroupState {
+        GroupState {
+            runs: self.runs,
+            expired: self.expired,
+            deferred_from: self.deferred_from,
+            deferred_to: self.deferred_from.and(self.next_execution)
+        }
+    }
+
+    /* Picks up where the group was, from the state it was saved with */
+    pub fn restore_state(&mut self, state: GroupState) {
+        self.runs = state.runs;
+        if state.expired {
+            self.expired = true;
+            self.next_execution = None;
+        } else if let (Some(from), Some(to)) = (state.deferred_from, state.deferred_to) {
+            self.deferred_from = Some(from);
+            self.next_nominal = Some(from);
+            self.next_execution = Some(to);
+        }
+    }
+
+    pub fn next_execution(&self) -> Option<DateTime<Utc>> {
+        self.next_execution
+    }
+
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expired
+    }
+
+    /* Expired, to be removed, and with nothing left running */
+    pub fn is_finished(&self) -> bool {
+        self.expired
+            && self.expire_action == ExpireAction::Remove
+            && self.active_runs.is_empty()
+            && self.delayed.is_empty()
+            && self.processes.iter().all(|task|
+                task.nb_running_tasks() == 0
+                    && task.nb_pending_retries() == 0
+                    && task.nb_waiting_for_idle() == 0
+            )
+    }
+
+    /* Whether the state changed since the last call */
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /* Takes over from a group of the same name, which is not scheduled
+     * anymore, and goes away once its tasks are over. Both share their
+     * directories, so the runs and the executions of each task go on from
+     * the numbers of the old group, and never use the same ones */
+    pub fn take_over(&mut self, old: &mut TaskGroup) {
+        self.runs = self.runs.max(old.runs);
+        for (task, old_task) in self.processes.iter_mut().zip(old.processes.iter()) {
+            task.continue_from(old_task);
+        }
+        old.expire_action = ExpireAction::Remove;
+        old.expire("replaced");
+    }
+
+    fn expire(&mut self, reason: &str) {
+        info!("\"{}\": Expired ({}), not scheduled anymore", self.name, reason);
+        self.expired = true;
+        self.next_execution = None;
+        self.deferred_from = None;
+        self.file_runs.clear();
+        self.dirty = true;
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.processes
+    }
+
+    fn task_label(&self, id: usize) -> String {
+        self.processes[id].name().unwrap_or(id.to_string())
+    }
+
+    fn task_id(&self, label: &str) -> Option<usize> {
+        (0 .. self.processes.len()).find(|x| self.task_label(*x) == label)
+    }
+
+    fn dependencies(&self, id: usize) -> Vec<usize> {
+        self.processes[id].dependencies()
+            .iter()
+            .filter_map(|x| self.task_id(x))
+            .collect()
+    }
+
+    /* Shared by the tasks of the run, and writable by all of them, like
+     * /tmp */
+    fn create_run_dir(&self, run_id: u64) -> io::Result<PathBuf> {
+        let runs_path = self.runs_path.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No directory for the runs"))?;
+        DirBuilder::new()
+            .recursive(true)
+            .mode(RUNS_MODE)
+            .create(runs_path)?;
+
+        let path = runs_path.join(run_id.to_string());
+        if path.symlink_metadata().is_ok() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o1777))?;
+        Ok(path)
+    }
+
+    fn start(&mut self, id: usize, mut ctx: RunContext) {
+        if let Some(run) = self.active_runs.get(&ctx.run_id) {
+            ctx.outputs = run.outputs.clone();
+            ctx.outputs_file = run.dir.as_ref()
+                .map(|x| x.join(format!("{}.outputs", id)));
+
+            if let Some(producer) = self.processes[id].stdin_task().and_then(|x| self.task_id(&x))
+                && let Some(Some(idx)) = run.finished.get(&producer) {
+                match self.processes[producer].stdout(*idx) {
+                Ok(stdout) => ctx.input = Some(stdout),
+                Err(e) => error!("\"{}\": Unable to read the stdout of \"{}\": {}", self.name, self.task_label(producer), e)
+                }
+            }
+        }
+
+        self.processes[id].run(ctx);
+    }
+
+    /* Records that the last attempt of a task is over, and starts the
+     * tasks that were waiting for it. */
+    fn finish(&mut self, id: usize, run_id: u64, idx: Option<usize>) {
+        let outputs = idx.map(|x| self.processes[id].outputs(x)).unwrap_or_default();
+        let Some(run) = self.active_runs.get_mut(&run_id) else {
+            return;
+        };
+        run.outputs.extend(outputs);
+        run.finished.insert(id, idx);
+
+        let waiting = std::mem::take(&mut self.waiting);
+        let mut ready = Vec::new();
+        for (task, ctx) in waiting {
+            let deps = self.dependencies(task);
+            let run = &self.active_runs[&run_id];
+            if ctx.run_id == run_id && deps.iter().all(|x| run.finished.contains_key(x)) {
+                let succeeded = deps.iter().all(|x| run.finished[x].is_some());
+                ready.push((task, ctx, succeeded));
             } else {
-                None
-            },
-        Some(period) => {
-            let mut next_execution = last_execution;
-            while next_execution < now {
-                next_execution = period.add(next_execution);
+                self.waiting.push((task, ctx));
+            }
+        }
+
+        for (task, ctx, succeeded) in ready {
+            if succeeded {
+                self.start(task, ctx);
+            } else {
+                warn!("\"{}\": Not running \"{}\", a task it depends on failed", self.name, ctx.task);
+                self.finish(task, run_id, None);
             }
-            self.next_execution = Some(next_execution);
         }
+
+        if self.active_runs.get(&run_id).is_some_and(|x| x.finished.len() == self.processes.len()) {
+            let run = self.active_runs.remove(&run_id).unwrap();
+            if let Some(dir) = run.dir
+                && let Err(e) = fs::remove_dir_all(&dir) {
+                error!("\"{}\": Unable to remove {:?}: {}", self.name, dir, e);
+            }
         }
     }
 
@@ -141,11 +699,53 @@ impl TaskGroup {
         let mut has_anything_changed = false;
 
         debug!("\"{}\": Updating", self.name);
-        for task in self.processes.iter_mut() {
+        for id in 0 .. self.processes.len() {
+            let task = &mut self.processes[id];
             has_anything_changed |= task.update();
+
+            for (event, details) in task.take_events() {
+                let mut hooks = task.hooks(event);
+                hooks.extend_from_slice(self.hooks.get(event));
+                hooks::fire(hooks, details, self.smtp.clone(), task.secrets());
+            }
+
+            for (run_id, idx, success) in task.take_finished_runs() {
+                self.finish(id, run_id, success.then_some(idx));
+            }
+        }
+
+        let (due, delayed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(time, _, _)| *time <= now);
+        self.delayed = delayed;
+        for (_, id, ctx) in due {
+            self.start(id, ctx);
         }
 
+        if !self.expired
+            && let Some(end) = self.ends_at
+            && (end <= now || self.next_execution.is_some_and(|x| x >= end)) {
+            self.expire("end date reached");
+        }
+
+        if !self.expired
+            && let Some(watcher) = &mut self.watcher {
+            match watcher.poll() {
+            Ok(paths) => {
+                let jitter = self.jitter(now);
+                self.file_runs.extend(paths.into_iter().map(|path| (now + jitter, now, path)));
+            },
+            Err(e) => error!("\"{}\": Unable to read the file events: {}", self.name, e)
+            }
+        }
+        has_anything_changed |= self.launch_file_runs(now);
+
         if self.next_execution.is_none() {
+            /* Like a one-shot group which ran */
+            if !self.expired && self.starts_at.is_some() && self.on_file.is_none() {
+                self.expire("no occurrence left");
+                return true;
+            }
             debug!("\"{}\": No update planned", self.name);
             return has_anything_changed;
         }
@@ -156,12 +756,116 @@ impl TaskGroup {
             return has_anything_changed;
         }
 
-        info!("\"{}\": Launching new tasks", self.name);
-        self.update_next_execution(now);
-        for task in self.processes.iter_mut() {
-            task.run();
+        /* The schedule goes on from the nominal time of the occurrence,
+         * whatever the jitter or the deferral */
+        let nominal = self.next_nominal.unwrap_or(next_execution);
+        let scheduled_time = match self.deferred_from.take() {
+            Some(nominal) => {
+                self.dirty = true;
+                nominal
+            },
+            None => match self.constraints.next_allowed(next_execution, &self.calendars) {
+                Ok(Some(time)) if time == next_execution => nominal,
+                Ok(Some(time)) if self.constraints.blackout_policy == BlackoutPolicy::Defer => {
+                    info!("\"{}\": Occurrence of {} deferred to {}", self.name, nominal, time);
+                    self.deferred_from = Some(nominal);
+                    self.next_execution = Some(time);
+                    self.dirty = true;
+                    return has_anything_changed;
+                },
+                result => {
+                    match result {
+                    Err(e) => error!("\"{}\": Skipping the occurrence of {}: {}", self.name, nominal, e),
+                    _ => info!("\"{}\": Skipping the occurrence of {}, outside of the allowed periods", self.name, nominal)
+                    }
+                    self.update_next_execution(Some(nominal));
+                    return has_anything_changed;
+                }
+            }
+        };
+
+        info!("\"{}\": Launching new tasks (scheduled at {}, fired at {})", self.name, scheduled_time, now);
+        self.update_next_execution(Some(scheduled_time));
+        self.launch(scheduled_time, now, None);
+        true
+    }
+
+    /* The runs for files are subject to the jitter and the calendars, like
+     * the occurrences of the schedule */
+    fn launch_file_runs(&mut self, now: DateTime<Utc>) -> bool {
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.file_runs)
+            .into_iter()
+            .partition(|(time, _, _)| *time <= now);
+        self.file_runs = pending;
+
+        let mut launched = false;
+        for (time, ready, path) in due {
+            if self.expired {
+                break;
+            }
+
+            match self.constraints.next_allowed(time, &self.calendars) {
+            Ok(Some(allowed)) if allowed == time => {
+                info!("\"{}\": Launching new tasks for {:?}", self.name, path);
+                self.launch(ready, now, Some(path));
+                launched = true;
+            },
+            Ok(Some(allowed)) if self.constraints.blackout_policy == BlackoutPolicy::Defer => {
+                info!("\"{}\": Run for {:?} deferred to {}", self.name, path, allowed);
+                self.file_runs.push((allowed, ready, path));
+            },
+            Err(e) => error!("\"{}\": Skipping the run for {:?}: {}", self.name, path, e),
+            _ => info!("\"{}\": Skipping the run for {:?}, outside of the allowed periods", self.name, path)
+            }
         }
+        launched
+    }
 
-        return true;
+    fn launch(&mut self, scheduled_time: DateTime<Utc>, now: DateTime<Utc>, trigger_path: Option<PathBuf>) {
+        self.runs += 1;
+        if let Some(max) = self.max_runs {
+            self.dirty = true;
+            if self.runs >= max {
+                self.expire("maximum number of runs reached");
+            }
+        }
+        let dir = match self.create_run_dir(self.runs) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                error!("\"{}\": Unable to create the run directory: {}", self.name, e);
+                None
+            }
+        };
+        self.active_runs.insert(self.runs, GroupRun {
+            dir: dir.clone(),
+            ..GroupRun::default()
+        });
+
+        /* Only the tasks started with the occurrence are spread, the
+         * others wait for their dependencies */
+        let roots: Vec<usize> = (0 .. self.processes.len())
+            .filter(|id| self.dependencies(*id).is_empty())
+            .collect();
+        for id in 0 .. self.processes.len() {
+            let ctx = RunContext {
+                group: self.name.clone(),
+                task: self.task_label(id),
+                run_id: self.runs,
+                scheduled_time,
+                attempt: 1,
+                run_dir: dir.clone(),
+                trigger_path: trigger_path.clone(),
+                ..RunContext::default()
+            };
+
+            match (roots.iter().position(|x| *x == id), self.spread) {
+            (Some(rank), Some(spread)) if rank > 0 => {
+                let offset = Duration::milliseconds((spread * 1000 * rank as u64 / roots.len() as u64) as i64);
+                self.delayed.push((now + offset, id, ctx));
+            },
+            (Some(_), _) => self.start(id, ctx),
+            (None, _) => self.waiting.push((id, ctx))
+            }
+        }
     }
 }
@@ -0,0 +1,252 @@
+use std::{collections::{BTreeMap, HashMap}, fs, io, thread};
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::{Command, Log, TaskOutput}, env::RunContext, notify::{self, SmtpRelay, Url}, secrets::SecretStore, template::{self, Value, Variables}
+};
+
+const STDERR_TAIL_LINES: usize = 20;
+const STDERR_TAIL_BYTES: usize = 4096;
+
+const DEFAULT_SUBJECT: &str = "[scheduler] {{group}}/{{task}}: {{event}}";
+const DEFAULT_BODY: &str = "Task \"{{task}}\" of \"{{group}}\" (run {{run_id}}, attempt {{attempt}}, \
+scheduled at {{scheduled_time}}) ended with {{status}}, exit code {{exit_code}}.
+
+Last lines of stderr:
+{{stderr_tail}}";
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Success,
+    Failure,
+    RetryExhausted,
+}
+
+/* The run details are given as JSON on the stdin of commands, and as the
+ * payload of webhooks, with the rendered message if any.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Hook {
+    Command(Box<Command>),
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        message: Option<String>,
+    },
+    Mail {
+        to: Vec<String>,
+        subject: Option<String>,
+        body: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_success: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_retry_exhausted: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn get(&self, event: HookEvent) -> &[Hook] {
+        match event {
+        HookEvent::Success => &self.on_success,
+        HookEvent::Failure => &self.on_failure,
+        HookEvent::RetryExhausted => &self.on_retry_exhausted,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let vars = RunDetails::default().template_variables();
+
+        for hook in self.on_success.iter()
+            .chain(self.on_failure.iter())
+            .chain(self.on_retry_exhausted.iter()) {
+            match hook {
+            Hook::Command(cmd) => cmd.validate()?,
+            Hook::Webhook { url, message, .. } => {
+                Url::parse(url)?;
+                if let Some(x) = message {
+                    template::validate(x, &vars)?;
+                }
+            },
+            Hook::Mail { subject, body, .. } => {
+                for x in [subject, body].into_iter().flatten() {
+                    template::validate(x, &vars)?;
+                }
+            }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RunDetails {
+    pub event: Option<HookEvent>,
+    pub group: String,
+    pub task: String,
+    pub run_id: u64,
+    pub attempt: u32,
+    pub scheduled_time: DateTime<Utc>,
+    #[serde(default)]
+    pub fire_time: DateTime<Utc>,
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub duration_ms: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+    pub stderr_tail: String,
+}
+
+fn tail(log: &[u8]) -> String {
+    let start = log.len().saturating_sub(STDERR_TAIL_BYTES);
+    let log = String::from_utf8_lossy(&log[start ..]);
+    let lines: Vec<&str> = log.lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES) ..].join("\n")
+}
+
+impl RunDetails {
+    pub fn new(event: HookEvent, ctx: &RunContext, output: &TaskOutput) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+
+        let outcome = output.outcome();
+        let stderr_tail = match outcome.map(|x| &x.stderr) {
+        Some(Log::Buffer(x)) => tail(x),
+        Some(Log::File(path)) => fs::read(path).map(|x| tail(&x)).unwrap_or_default(),
+        _ => String::new()
+        };
+
+        Self {
+            event: Some(event),
+            group: ctx.group.clone(),
+            task: ctx.task.clone(),
+            run_id: ctx.run_id,
+            attempt: ctx.attempt,
+            scheduled_time: ctx.scheduled_time,
+            fire_time: ctx.fire_time,
+            status: output.summary(),
+            exit_code: outcome.and_then(|x| x.exit_status.code()),
+            signal: outcome.and_then(|x| x.exit_status.signal()),
+            duration_ms: outcome.map(|x| x.duration.as_millis()),
+            script_hash: outcome.and_then(|x| x.script_hash.clone()),
+            outputs: outcome.map(|x| x.outputs.clone()).unwrap_or_default(),
+            stderr_tail
+        }
+    }
+
+    pub fn template_variables(&self) -> Variables {
+        let text = |x: &dyn ToString| Value::Text(x.to_string());
+        let event = match self.event {
+        Some(HookEvent::Success) => "success",
+        Some(HookEvent::Failure) => "failure",
+        Some(HookEvent::RetryExhausted) => "retry exhausted",
+        None => ""
+        };
+
+        [
+            ("event", text(&event)),
+            ("group", text(&self.group)),
+            ("task", text(&self.task)),
+            ("run_id", text(&self.run_id)),
+            ("attempt", text(&self.attempt)),
+            ("scheduled_time", Value::Time(Some(self.scheduled_time))),
+            ("fire_time", Value::Time(Some(self.fire_time))),
+            ("status", text(&self.status)),
+            ("exit_code", text(&self.exit_code.map_or(String::from("none"), |x| x.to_string()))),
+            ("signal", text(&self.signal.map_or(String::from("none"), |x| x.to_string()))),
+            ("duration_ms", text(&self.duration_ms.map_or(String::from("none"), |x| x.to_string()))),
+            ("stderr_tail", text(&self.stderr_tail)),
+        ].into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect()
+    }
+
+    fn context(&self) -> RunContext {
+        RunContext {
+            group: self.group.clone(),
+            task: self.task.clone(),
+            run_id: self.run_id,
+            scheduled_time: self.scheduled_time,
+            fire_time: self.fire_time,
+            attempt: self.attempt,
+            ..RunContext::default()
+        }
+    }
+}
+
+fn render(value: &str, vars: &Variables) -> io::Result<String> {
+    template::render(value, vars)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+impl Hook {
+    /* The commands resolve their secrets from the store of the group */
+    pub fn run(&self, details: &RunDetails, smtp: Option<&SmtpRelay>, secrets: Option<&SecretStore>) -> io::Result<()> {
+        let vars = details.template_variables();
+        let mut payload = serde_json::to_value(details).unwrap();
+
+        match self {
+        Hook::Command(cmd) => {
+            let ctx = RunContext {
+                secrets: secrets.cloned(),
+                ..details.context()
+            };
+            let output = cmd.run_with_input(&ctx, Some(payload.to_string().into_bytes()));
+            match output {
+            TaskOutput::NoError(x) if x.is_success() => Ok(()),
+            output => Err(io::Error::other(format!("Hook command failed: {}", output.summary())))
+            }
+        },
+        Hook::Webhook { url, headers, message } => {
+            if let Some(message) = message {
+                payload["message"] = render(message, &vars)?.into();
+            }
+            let url = Url::parse(url)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            notify::post_json(&url, headers, payload.to_string().as_bytes())
+        },
+        Hook::Mail { to, subject, body } => {
+            let smtp = smtp.ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                "No SMTP relay is configured"
+            ))?;
+            notify::send_mail(
+                smtp,
+                to,
+                &render(subject.as_deref().unwrap_or(DEFAULT_SUBJECT), &vars)?,
+                &render(body.as_deref().unwrap_or(DEFAULT_BODY), &vars)?
+            )
+        }
+        }
+    }
+}
+
+/* Runs the hooks in the background, not to hold the scheduler */
+pub fn fire(hooks: Vec<Hook>, details: RunDetails, smtp: Option<SmtpRelay>, secrets: Option<SecretStore>) {
+    if hooks.is_empty() {
+        return;
+    }
+
+    thread::spawn(move || {
+        for hook in hooks.iter() {
+            match hook.run(&details, smtp.as_ref(), secrets.as_ref()) {
+            Ok(()) => info!("\"{}\": Ran a hook of \"{}\"", details.group, details.task),
+            Err(e) => error!("\"{}\": A hook of \"{}\" failed: {}", details.group, details.task, e)
+            }
+        }
+    });
+}
@@ -0,0 +1,72 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use log::info;
+
+use crate::{group::{Provenance, SerializedTaskGroup, TaskGroup}, watch::glob_to_regex};
+
+/* Files of the include directory: every .json file of a directory, or the
+ * files whose names match a pattern, like "*.conf" in a given directory.
+ * Hidden files are skipped, unless the pattern starts with a dot.
+ */
+fn list_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let (dir, pattern) = if path.is_dir() {
+        (path, String::from("*.json"))
+    } else {
+        let dir = path.parent()
+            .filter(|x| !x.as_os_str().is_empty())
+            .ok_or_else(|| format!("No directory in {:?}", path))?;
+        let name = path.file_name()
+            .ok_or_else(|| format!("No file name in {:?}", path))?;
+        (dir, name.to_string_lossy().into_owned())
+    };
+    let hidden = pattern.starts_with('.');
+    let pattern = glob_to_regex(&pattern)?;
+
+    let mut out: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{:?}: {}", dir, e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            path.is_file()
+                && (hidden || !name.starts_with('.'))
+                && pattern.is_match(&name)
+        })
+        .collect();
+    out.sort();
+    Ok(out)
+}
+
+/* A file holds a group, or an array of groups */
+fn read_file(path: &Path) -> Result<Vec<SerializedTaskGroup>, String> {
+    let content = fs::read(path).map_err(|e| e.to_string())?;
+    let value: serde_json::Value = serde_json::from_slice(&content)
+        .map_err(|e| e.to_string())?;
+    if value.is_array() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|x| vec![x])
+    }.map_err(|e| e.to_string())
+}
+
+/* The groups of the included files, in the lexical order of the files.
+ * They are marked as coming from their file, so that the server never
+ * saves them, only their state, by name, to restore after loading them.
+ */
+pub fn load(path: &Path) -> Result<Vec<TaskGroup>, String> {
+    let mut out = Vec::new();
+    for file in list_files(path)? {
+        let groups = read_file(&file)
+            .map_err(|e| format!("{:?}: {}", file, e))?;
+        info!("Including {} group(s) from {:?}", groups.len(), file);
+
+        for stg in groups {
+            stg.validate()
+                .map_err(|e| format!("{:?}: {}", file, e))?;
+            let mut group = TaskGroup::from(stg);
+            group.set_provenance(Provenance::Include(file.clone()));
+            out.push(group);
+        }
+    }
+    Ok(out)
+}
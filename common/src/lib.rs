@@ -6,4 +6,22 @@ pub mod task;
 pub mod utils;
 pub mod group;
 pub mod log;
-pub mod queries;
\ No newline at end of file
+pub mod queries;
+pub mod limits;
+pub mod cgroup;
+pub mod sandbox;
+pub mod user;
+pub mod env;
+pub mod template;
+pub mod secrets;
+pub mod notify;
+pub mod hooks;
+pub mod metrics;
+pub mod success;
+pub mod script;
+pub mod calendar;
+pub mod at;
+pub mod load;
+pub mod watch;
+pub mod spool;
+pub mod include;
@@ -0,0 +1,160 @@
+use std::io;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoPriorityClass {
+    Realtime,
+    BestEffort,
+    Idle
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct IoPriority {
+    pub class: IoPriorityClass,
+    #[serde(default)]
+    pub level: u8
+}
+
+impl IoPriority {
+    /* Encoding of the value expected by ioprio_set(2), see
+     * include/uapi/linux/ioprio.h
+     */
+    fn value(&self) -> libc::c_int {
+        let class = match self.class {
+        IoPriorityClass::Realtime => 1,
+        IoPriorityClass::BestEffort => 2,
+        IoPriorityClass::Idle => 3,
+        };
+        (class << 13) | (self.level as libc::c_int)
+    }
+}
+
+/* Limits applied to the spawned process right before exec. The rlimits
+ * set both the soft and the hard limit.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Limits {
+    #[serde(rename = "as")]
+    pub address_space: Option<u64>,
+    #[serde(rename = "cpu")]
+    pub cpu_time: Option<u64>,
+    #[serde(rename = "nofile")]
+    pub open_files: Option<u64>,
+    #[serde(rename = "nproc")]
+    pub processes: Option<u64>,
+    #[serde(rename = "core")]
+    pub core_size: Option<u64>,
+    pub nice: Option<i32>,
+    pub ioprio: Option<IoPriority>,
+    pub cpu_affinity: Option<Vec<usize>>,
+    #[serde(default)]
+    #[serde(serialize_with = "serialize_umask", deserialize_with = "deserialize_umask")]
+    pub umask: Option<u32>,
+}
+
+/* The umask is written as an octal string ("0027"), since JSON has no
+ * octal literal.
+ */
+fn serialize_umask<S>(umask: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+where S: Serializer {
+    umask.map(|x| format!("{:04o}", x)).serialize(serializer)
+}
+
+fn deserialize_umask<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where D: Deserializer<'de> {
+    match Option::<String>::deserialize(deserializer)? {
+    None => Ok(None),
+    Some(x) => u32::from_str_radix(&x, 8)
+        .ok()
+        .filter(|x| *x <= 0o777)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("Invalid umask: {}", x)))
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn set_rlimit(resource: libc::__rlimit_resource_t, value: Option<u64>) -> io::Result<()> {
+    if let Some(value) = value {
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        check(unsafe { libc::setrlimit(resource, &limit) })?;
+    }
+    Ok(())
+}
+
+impl Limits {
+    /* Checked again when applied, but reported at load time */
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ioprio) = &self.ioprio
+            && ioprio.level > 7 {
+            return Err(format!("Invalid I/O priority level {}, it ranges from 0 to 7", ioprio.level));
+        }
+
+        if let Some(cpus) = &self.cpu_affinity {
+            if cpus.is_empty() {
+                return Err(String::from("Empty CPU affinity"));
+            }
+            if let Some(cpu) = cpus.iter().find(|x| **x >= libc::CPU_SETSIZE as usize) {
+                return Err(format!("Invalid CPU {}, the affinity takes CPUs below {}", cpu, libc::CPU_SETSIZE));
+            }
+        }
+
+        Ok(())
+    }
+
+    /* Meant to be called from a pre_exec hook: it must stay
+     * async-signal-safe, so nothing in here allocates.
+     */
+    pub fn apply(&self) -> io::Result<()> {
+        set_rlimit(libc::RLIMIT_AS, self.address_space)?;
+        set_rlimit(libc::RLIMIT_CPU, self.cpu_time)?;
+        set_rlimit(libc::RLIMIT_NOFILE, self.open_files)?;
+        set_rlimit(libc::RLIMIT_NPROC, self.processes)?;
+        set_rlimit(libc::RLIMIT_CORE, self.core_size)?;
+
+        if let Some(nice) = self.nice {
+            check(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })?;
+        }
+
+        if let Some(ioprio) = &self.ioprio {
+            if ioprio.level > 7 {
+                return Err(io::Error::from(io::ErrorKind::InvalidInput));
+            }
+            /* IOPRIO_WHO_PROCESS = 1, the pid 0 being the calling process */
+            let ret = unsafe {
+                libc::syscall(libc::SYS_ioprio_set, 1, 0, ioprio.value())
+            };
+            check(ret as libc::c_int)?;
+        }
+
+        if let Some(cpus) = &self.cpu_affinity {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for cpu in cpus.iter() {
+                if *cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(io::Error::from(io::ErrorKind::InvalidInput));
+                }
+                unsafe { libc::CPU_SET(*cpu, &mut set) };
+            }
+            check(unsafe {
+                libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set)
+            })?;
+        }
+
+        if let Some(umask) = self.umask {
+            unsafe { libc::umask(umask as libc::mode_t) };
+        }
+
+        Ok(())
+    }
+}
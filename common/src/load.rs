@@ -0,0 +1,102 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+/* Load of the system, as the kernel reports it. Values that can't be read,
+ * like pressures on kernels without PSI, are missing.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SystemLoad {
+    /* Over the last minute */
+    pub loadavg: Option<f64>,
+    /* Percentage of the last 10 seconds some tasks were stalled */
+    pub cpu_pressure: Option<f64>,
+    pub memory_pressure: Option<f64>,
+    pub io_pressure: Option<f64>,
+}
+
+/* First field of /proc/loadavg */
+pub fn parse_loadavg(content: &str) -> Option<f64> {
+    content.split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/* avg10 of the "some" line of a /proc/pressure file */
+pub fn parse_pressure(content: &str) -> Option<f64> {
+    content.lines()
+        .find_map(|line| line.strip_prefix("some "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+impl SystemLoad {
+    pub fn read() -> Self {
+        let read = |path: &str, parse: fn(&str) -> Option<f64>| fs::read_to_string(path)
+            .ok()
+            .and_then(|x| parse(&x));
+
+        Self {
+            loadavg: read("/proc/loadavg", parse_loadavg),
+            cpu_pressure: read("/proc/pressure/cpu", parse_pressure),
+            memory_pressure: read("/proc/pressure/memory", parse_pressure),
+            io_pressure: read("/proc/pressure/io", parse_pressure),
+        }
+    }
+}
+
+/* Executions only start once the system is idle enough, or when they
+ * waited for max_delay seconds. Conditions on values that can't be read
+ * are taken as met.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RunWhen {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_loadavg: Option<f64>,
+    /* Percentages, compared to the avg10 of the "some" pressures */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu_pressure: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_pressure: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_io_pressure: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay: Option<u64>,
+}
+
+/* Threshold, and the value it applies to */
+type Threshold = (Option<f64>, fn(&SystemLoad) -> Option<f64>);
+
+impl RunWhen {
+    fn thresholds(&self) -> [Threshold; 4] {
+        [
+            (self.max_loadavg, |x| x.loadavg),
+            (self.max_cpu_pressure, |x| x.cpu_pressure),
+            (self.max_memory_pressure, |x| x.memory_pressure),
+            (self.max_io_pressure, |x| x.io_pressure),
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let thresholds = self.thresholds();
+        if thresholds.iter().all(|(max, _)| max.is_none()) {
+            return Err(String::from("run_when has no condition"));
+        }
+        if thresholds.iter().any(|(max, _)| max.is_some_and(|x| x.is_nan() || x < 0.)) {
+            return Err(String::from("run_when thresholds must be positive"));
+        }
+        Ok(())
+    }
+
+    pub fn is_met(&self, load: &SystemLoad) -> bool {
+        self.thresholds()
+            .iter()
+            .all(|(max, value)| match (max, value(load)) {
+            (Some(max), Some(value)) => value <= *max,
+            _ => true
+            })
+    }
+}
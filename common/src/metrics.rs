@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::command::TaskOutput;
+
+/* Upper bounds of the duration histogram, in seconds */
+pub const DURATION_BUCKETS: [f64; 12] = [
+    0.1, 0.5, 1., 5., 10., 30., 60., 300., 600., 1800., 3600., 14400.
+];
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /* One more than the bounds, for the +Inf bucket */
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let idx = self.bounds.iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[idx] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /* Cumulative counts, as exposed by Prometheus */
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.bounds.iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(self.counts.iter().scan(0, |acc, x| {
+                *acc += x;
+                Some(*acc)
+            }))
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/* Streaming quantile estimation, with a bounded relative error: values
+ * are counted in logarithmic buckets, whose width grows with the value.
+ */
+#[derive(Debug, Clone)]
+pub struct QuantileSketch {
+    gamma: f64,
+    buckets: BTreeMap<i32, u64>,
+    /* Values too small to be put in a bucket */
+    zero_count: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+const SKETCH_MIN_VALUE: f64 = 1e-9;
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::new(0.01)
+    }
+}
+
+impl QuantileSketch {
+    pub fn new(relative_accuracy: f64) -> Self {
+        Self {
+            gamma: (1. + relative_accuracy) / (1. - relative_accuracy),
+            buckets: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        if value < SKETCH_MIN_VALUE {
+            self.zero_count += 1;
+        } else {
+            let idx = (value.ln() / self.gamma.ln()).ceil() as i32;
+            *self.buckets.entry(idx).or_insert(0) += 1;
+        }
+
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /* q is between 0 and 1 */
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (q.clamp(0., 1.) * (self.count - 1) as f64).round() as u64;
+        if rank < self.zero_count {
+            return Some(self.min);
+        }
+
+        let mut seen = self.zero_count;
+        for (idx, count) in self.buckets.iter() {
+            seen += count;
+            if seen > rank {
+                let value = 2. * self.gamma.powi(*idx) / (self.gamma + 1.);
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
+
+/* Counters of a task since the server started */
+#[derive(Debug, Clone)]
+pub struct TaskMetrics {
+    /* Executions that were started, retries included */
+    pub runs: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    /* Executions that were not started because of max_concurrent_execution */
+    pub skipped: u64,
+    pub duration: Histogram,
+}
+
+impl Default for TaskMetrics {
+    fn default() -> Self {
+        Self {
+            runs: 0,
+            failures: 0,
+            timeouts: 0,
+            skipped: 0,
+            duration: Histogram::new(&DURATION_BUCKETS),
+        }
+    }
+}
+
+impl TaskMetrics {
+    pub fn record(&mut self, output: &TaskOutput) {
+        match output {
+        TaskOutput::Waiting => return,
+        TaskOutput::TooManyThreadsError => {
+            self.skipped += 1;
+            return;
+        },
+        TaskOutput::TimedOut(_) => self.timeouts += 1,
+        _ => {}
+        }
+
+        self.runs += 1;
+        if !matches!(output, TaskOutput::NoError(x) if x.is_success()) {
+            self.failures += 1;
+        }
+        if let Some(outcome) = output.outcome() {
+            self.duration.observe(outcome.duration.as_secs_f64());
+        }
+    }
+}
+
+/* Writer for the Prometheus text exposition format. The samples of a
+ * family must follow its header.
+ */
+#[derive(Default)]
+pub struct Exposition {
+    buffer: String,
+}
+
+pub type Labels<'a> = [(&'a str, &'a str)];
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value == f64::INFINITY {
+        String::from("+Inf")
+    } else if value == f64::NEG_INFINITY {
+        String::from("-Inf")
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+        writeln!(self.buffer, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.buffer, "# TYPE {} {}", name, kind).unwrap();
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &Labels, value: f64) {
+        self.buffer.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            write!(self.buffer, "{{{}}}", labels.join(",")).unwrap();
+        }
+        writeln!(self.buffer, " {}", format_value(value)).unwrap();
+    }
+
+    pub fn histogram(&mut self, name: &str, labels: &Labels, histogram: &Histogram) {
+        let bucket = format!("{}_bucket", name);
+        for (bound, count) in histogram.buckets() {
+            let le = format_value(bound);
+            let mut labels = labels.to_vec();
+            labels.push(("le", le.as_str()));
+            self.sample(&bucket, &labels, count as f64);
+        }
+        self.sample(&format!("{}_sum", name), labels, histogram.sum());
+        self.sample(&format!("{}_count", name), labels, histogram.count() as f64);
+    }
+
+    pub fn finish(self) -> String {
+        self.buffer
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap, io::{self, BufRead, BufReader, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::from(io::ErrorKind::AddrNotAvailable);
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+        Ok(stream) => {
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            return Ok(stream);
+        },
+        Err(e) => last_error = e
+        }
+    }
+    Err(last_error)
+}
+
+/* Only plain http:// URLs are supported, TLS is left to a local relay */
+#[derive(Debug, PartialEq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("Only http:// URLs are supported: {}", url))?;
+
+        let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[.. idx], &rest[idx ..]),
+        None => (rest, "/")
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse()
+            .map_err(|_| format!("Invalid port in {}", url))?),
+        None => (authority, 80)
+        };
+
+        if host.is_empty() {
+            return Err(format!("Missing host in {}", url));
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string()
+        })
+    }
+}
+
+/* POSTs a JSON body, failing unless the answer is a 2xx */
+pub fn post_json(url: &Url, headers: &HashMap<String, String>, body: &[u8]) -> io::Result<()> {
+    let mut stream = connect(&url.host, url.port)?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path, url.host, url.port, body.len()
+    );
+    for (k, v) in headers.iter() {
+        request.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    let code = status.split_whitespace()
+        .nth(1)
+        .and_then(|x| x.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid HTTP answer: {}", status.trim())))?;
+
+    if (200 .. 300).contains(&code) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("HTTP error: {}", status.trim())))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SmtpRelay {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub from: String,
+    #[serde(default = "default_hello")]
+    pub hello: String,
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+fn default_hello() -> String {
+    String::from("localhost")
+}
+
+struct SmtpSession<R: Read, W: Write> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+impl<R: Read, W: Write> SmtpSession<R, W> {
+    /* Reads a possibly multiline reply, checking its code */
+    fn expect(&mut self, code: u16) -> io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+
+            if !line.starts_with(&code.to_string()) {
+                return Err(io::Error::other(format!("SMTP error: {}", line.trim())));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, code: u16) -> io::Result<()> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\r\n")?;
+        self.expect(code)
+    }
+}
+
+pub fn send_mail(relay: &SmtpRelay, to: &[String], subject: &str, body: &str) -> io::Result<()> {
+    if to.is_empty() {
+        return Err(invalid_input(String::from("No recipient")));
+    }
+
+    let stream = connect(&relay.host, relay.port)?;
+    let mut session = SmtpSession {
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream
+    };
+
+    session.expect(220)?;
+    session.command(&format!("HELO {}", relay.hello), 250)?;
+    session.command(&format!("MAIL FROM:<{}>", relay.from), 250)?;
+    for x in to.iter() {
+        session.command(&format!("RCPT TO:<{}>", x), 250)?;
+    }
+    session.command("DATA", 354)?;
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        relay.from, to.join(", "), subject.replace(['\r', '\n'], " "), Utc::now().to_rfc2822()
+    );
+    for line in body.lines() {
+        /* Dot-stuffing, see RFC 5321 4.5.2 */
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+
+    session.command(&message, 250)?;
+    session.command("QUIT", 221)
+}
@@ -1,9 +1,26 @@
 use serde::{Deserialize, Serialize};
 
-use crate::group::SerializedTaskGroup;
+use crate::{at::{AtJobSummary, AtRequest}, group::SerializedTaskGroup, task::StatisticSummary};
+
+#[derive(Deserialize, Serialize)]
+pub struct TaskStatisticReport {
+    pub group: String,
+    pub task: String,
+    pub statistics: StatisticSummary
+}
 
 #[derive(Deserialize, Serialize)]
 pub enum Queries {
     Ok,
-    NewTaskGroup(SerializedTaskGroup)
+    Error(String),
+    NewTaskGroup(Box<SerializedTaskGroup>),
+    /* Statistics of every task, or only of the given group's */
+    GetStatistics(Option<String>),
+    Statistics(Vec<TaskStatisticReport>),
+    /* One-shot executions: queued with At, answered by the job's id */
+    At(Box<AtRequest>),
+    AtQueued(u64),
+    ListAt,
+    AtJobs(Vec<AtJobSummary>),
+    CancelAt(u64)
 }
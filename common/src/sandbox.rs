@@ -0,0 +1,292 @@
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+/* Opt-in isolation of a command, relying on Linux namespaces only. Mount
+ * related options (read_only, private_tmp) imply a mount namespace.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Sandbox {
+    #[serde(default)]
+    pub mount: bool,
+    #[serde(default)]
+    pub pid: bool,
+    #[serde(default)]
+    pub network: bool,
+    #[serde(default)]
+    pub ipc: bool,
+    #[serde(default)]
+    pub uts: bool,
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub read_only: Vec<PathBuf>,
+    #[serde(default)]
+    pub private_tmp: bool,
+    #[serde(default = "default_true")]
+    pub no_new_privs: bool,
+    #[serde(default)]
+    pub seccomp_deny: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc00000b7;
+
+fn syscall_number(name: &str) -> Option<libc::c_long> {
+    Some(match name {
+    "acct" => libc::SYS_acct,
+    "add_key" => libc::SYS_add_key,
+    "bpf" => libc::SYS_bpf,
+    "chroot" => libc::SYS_chroot,
+    "clock_settime" => libc::SYS_clock_settime,
+    "delete_module" => libc::SYS_delete_module,
+    "finit_module" => libc::SYS_finit_module,
+    "init_module" => libc::SYS_init_module,
+    "kexec_load" => libc::SYS_kexec_load,
+    "keyctl" => libc::SYS_keyctl,
+    "mount" => libc::SYS_mount,
+    "open_by_handle_at" => libc::SYS_open_by_handle_at,
+    "perf_event_open" => libc::SYS_perf_event_open,
+    "personality" => libc::SYS_personality,
+    "pivot_root" => libc::SYS_pivot_root,
+    "process_vm_readv" => libc::SYS_process_vm_readv,
+    "process_vm_writev" => libc::SYS_process_vm_writev,
+    "ptrace" => libc::SYS_ptrace,
+    "reboot" => libc::SYS_reboot,
+    "request_key" => libc::SYS_request_key,
+    "setdomainname" => libc::SYS_setdomainname,
+    "sethostname" => libc::SYS_sethostname,
+    "setns" => libc::SYS_setns,
+    "settimeofday" => libc::SYS_settimeofday,
+    "swapoff" => libc::SYS_swapoff,
+    "swapon" => libc::SYS_swapon,
+    "umount2" => libc::SYS_umount2,
+    "unshare" => libc::SYS_unshare,
+    "userfaultfd" => libc::SYS_userfaultfd,
+    _ => return None
+    })
+}
+
+fn bpf(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt, jf, k }
+}
+
+/* Filter returning EPERM for the denied syscalls, and killing the process
+ * if it uses another syscall ABI than the one the numbers come from.
+ */
+fn seccomp_filter(denied: &[String]) -> io::Result<Vec<libc::sock_filter>> {
+    if denied.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut filter = vec![
+        bpf(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4, 0, 0),
+        bpf(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+        bpf(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS, 0, 0),
+        bpf(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0, 0, 0),
+    ];
+
+    for name in denied.iter() {
+        let nr = syscall_number(name)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown syscall: {}", name)
+            ))?;
+        filter.push(bpf(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr as u32, 0, 1));
+        filter.push(bpf(libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32), 0, 0));
+    }
+
+    filter.push(bpf(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW, 0, 0));
+    Ok(filter)
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/* Everything that requires an allocation is done here, before the fork */
+#[derive(Debug)]
+pub struct PreparedSandbox {
+    flags: libc::c_int,
+    hostname: Option<CString>,
+    read_only: Vec<CString>,
+    private_tmp: bool,
+    no_new_privs: bool,
+    filter: Vec<libc::sock_filter>,
+}
+
+impl Sandbox {
+    pub fn prepare(&self) -> io::Result<PreparedSandbox> {
+        let mount = self.mount || self.private_tmp || !self.read_only.is_empty();
+        let mut flags = 0;
+        for (enabled, flag) in [
+            (mount, libc::CLONE_NEWNS),
+            (self.pid, libc::CLONE_NEWPID),
+            (self.network, libc::CLONE_NEWNET),
+            (self.ipc, libc::CLONE_NEWIPC),
+            (self.uts || self.hostname.is_some(), libc::CLONE_NEWUTS),
+        ] {
+            if enabled {
+                flags |= flag;
+            }
+        }
+
+        Ok(PreparedSandbox {
+            flags,
+            hostname: self.hostname.as_ref()
+                .map(|x| CString::new(x.as_str()))
+                .transpose()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            read_only: self.read_only.iter()
+                .map(|x| path_to_cstring(x))
+                .collect::<io::Result<_>>()?,
+            private_tmp: self.private_tmp,
+            no_new_privs: self.no_new_privs,
+            filter: seccomp_filter(&self.seccomp_deny)?,
+        })
+    }
+}
+
+/* Waits for the sandboxed process and exits the same way it did */
+fn forward_exit(pid: libc::pid_t) -> ! {
+    unsafe {
+        /* Don't keep the pipe std uses to report exec errors open */
+        libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0);
+
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            if *libc::__errno_location() != libc::EINTR {
+                libc::_exit(127);
+            }
+        }
+
+        if libc::WIFSIGNALED(status) {
+            let sig = libc::WTERMSIG(status);
+            libc::signal(sig, libc::SIG_DFL);
+            libc::kill(libc::getpid(), sig);
+            libc::_exit(128 + sig);
+        }
+        libc::_exit(libc::WEXITSTATUS(status))
+    }
+}
+
+fn loopback_up() -> io::Result<()> {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        check(fd)?;
+
+        let mut req: libc::ifreq = std::mem::zeroed();
+        req.ifr_name[0] = b'l' as libc::c_char;
+        req.ifr_name[1] = b'o' as libc::c_char;
+
+        let mut ret = libc::ioctl(fd, libc::SIOCGIFFLAGS as _, &mut req);
+        if ret >= 0 {
+            req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            ret = libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &req);
+        }
+        libc::close(fd);
+        check(ret)
+    }
+}
+
+impl PreparedSandbox {
+    /* Enters the namespaces. Meant to be called from a pre_exec hook,
+     * while the process is still privileged.
+     */
+    pub fn enter(&self) -> io::Result<()> {
+        if self.flags == 0 {
+            return Ok(());
+        }
+        check(unsafe { libc::unshare(self.flags) })?;
+
+        let mount = self.flags & libc::CLONE_NEWNS != 0;
+        if mount {
+            check(unsafe { libc::mount(
+                std::ptr::null(), c"/".as_ptr(), std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()
+            ) })?;
+
+            for path in self.read_only.iter() {
+                check(unsafe { libc::mount(
+                    path.as_ptr(), path.as_ptr(), std::ptr::null(),
+                    libc::MS_BIND | libc::MS_REC, std::ptr::null()
+                ) })?;
+                check(unsafe { libc::mount(
+                    std::ptr::null(), path.as_ptr(), std::ptr::null(),
+                    libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY, std::ptr::null()
+                ) })?;
+            }
+
+            if self.private_tmp {
+                check(unsafe { libc::mount(
+                    c"tmpfs".as_ptr(), c"/tmp".as_ptr(), c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    c"mode=1777".as_ptr() as *const libc::c_void
+                ) })?;
+            }
+        }
+
+        if let Some(hostname) = &self.hostname {
+            check(unsafe {
+                libc::sethostname(hostname.as_ptr(), hostname.as_bytes().len())
+            })?;
+        }
+
+        if self.flags & libc::CLONE_NEWNET != 0 {
+            loopback_up()?;
+        }
+
+        /* Only the children of the caller end up in the new PID namespace */
+        if self.flags & libc::CLONE_NEWPID != 0 {
+            let pid = unsafe { libc::fork() };
+            check(pid)?;
+            if pid > 0 {
+                forward_exit(pid);
+            }
+
+            if mount {
+                check(unsafe { libc::mount(
+                    c"proc".as_ptr(), c"/proc".as_ptr(), c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    std::ptr::null()
+                ) })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /* Restrictions applied last, once the privileges are dropped */
+    pub fn restrict(&self) -> io::Result<()> {
+        if self.no_new_privs {
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        }
+
+        if !self.filter.is_empty() {
+            let prog = libc::sock_fprog {
+                len: self.filter.len() as libc::c_ushort,
+                filter: self.filter.as_ptr() as *mut libc::sock_filter,
+            };
+            check(unsafe { libc::prctl(
+                libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &prog
+            ) })?;
+        }
+
+        Ok(())
+    }
+}
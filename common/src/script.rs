@@ -0,0 +1,63 @@
+use std::{
+    fs::{self, OpenOptions}, io::{self, Write}, os::unix::fs::{fchown, OpenOptionsExt}, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::user::Credentials;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/* Body of an inline script, with its templates rendered */
+pub struct Script {
+    pub body: Vec<u8>,
+    /* Hexadecimal SHA-256 of the body */
+    pub hash: String,
+}
+
+/* Temporary copy of a script, removed when dropped */
+pub struct ScriptFile {
+    path: PathBuf,
+}
+
+impl Script {
+    pub fn new(body: Vec<u8>) -> Self {
+        let hash = Sha256::digest(&body)
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect();
+        Self { body, hash }
+    }
+
+    /* Only readable by the user the execution runs as */
+    pub fn write(&self, credentials: &Credentials) -> io::Result<ScriptFile> {
+        let path = std::env::temp_dir().join(format!(
+            "scheduler-script-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o400)
+            .open(&path)?;
+        let out = ScriptFile { path };
+
+        fchown(&file, credentials.uid, credentials.gid)?;
+        file.write_all(&self.body)?;
+        Ok(out)
+    }
+}
+
+impl ScriptFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
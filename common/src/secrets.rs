@@ -0,0 +1,148 @@
+use std::{collections::HashMap, fs, io, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
+
+use chacha20poly1305::{aead::{Aead, KeyInit}, ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const MASK: &[u8] = b"********";
+
+/* Where the secrets referenced by the commands are read from, at spawn
+ * time. Either a directory holding one file per secret, or a JSON object
+ * of secrets encrypted with ChaCha20-Poly1305: a 12 bytes nonce followed
+ * by the ciphertext, the key being 32 raw bytes in its own file.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretStore {
+    Directory(PathBuf),
+    EncryptedFile {
+        path: PathBuf,
+        key: PathBuf
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/* Secrets must only be readable by the server */
+fn check_private(path: &Path) -> io::Result<()> {
+    let meta = fs::metadata(path)?;
+    let owner = unsafe { libc::geteuid() };
+
+    if meta.mode() & 0o077 != 0 || (meta.uid() != owner && meta.uid() != 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} must only be accessible by its owner", path.display())
+        ));
+    }
+    Ok(())
+}
+
+fn read_key(path: &Path) -> io::Result<Vec<u8>> {
+    check_private(path)?;
+    let key = fs::read(path)?;
+    if key.len() != KEY_LEN {
+        return Err(invalid_data(format!("{}: the key must be {} bytes long", path.display(), KEY_LEN)));
+    }
+    Ok(key)
+}
+
+/* Encrypts a JSON object of secrets, in the format expected by
+ * SecretStore::EncryptedFile.
+ */
+pub fn seal(key_path: &Path, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let key = read_key(key_path)?;
+    serde_json::from_slice::<HashMap<String, String>>(plaintext)
+        .map_err(|e| invalid_data(e.to_string()))?;
+
+    let mut nonce = [0; NONCE_LEN];
+    getrandom(&mut nonce)?;
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| invalid_data(String::from("Unable to encrypt the secrets")))?;
+
+    Ok([nonce.as_slice(), ciphertext.as_slice()].concat())
+}
+
+fn getrandom(buffer: &mut [u8]) -> io::Result<()> {
+    let ret = unsafe {
+        libc::getrandom(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0)
+    };
+    if ret < 0 || ret as usize != buffer.len() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl SecretStore {
+    pub fn resolve(&self, name: &str) -> io::Result<String> {
+        let not_found = || io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Unknown secret: {}", name)
+        );
+
+        match self {
+        SecretStore::Directory(dir) => {
+            if name.is_empty() || name.contains('/') || name.starts_with('.') {
+                return Err(not_found());
+            }
+
+            check_private(dir)?;
+            let path = dir.join(name);
+            if !path.exists() {
+                return Err(not_found());
+            }
+            check_private(&path)?;
+
+            let value = fs::read_to_string(&path)?;
+            Ok(value.strip_suffix('\n').unwrap_or(&value).to_string())
+        },
+        SecretStore::EncryptedFile { path, key } => {
+            let key = read_key(key)?;
+            let content = fs::read(path)?;
+            if content.len() < NONCE_LEN {
+                return Err(invalid_data(format!("{}: truncated file", path.display())));
+            }
+
+            let (nonce, ciphertext) = content.split_at(NONCE_LEN);
+            let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+            let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| invalid_data(format!("{}: unable to decrypt", path.display())))?;
+
+            let mut secrets: HashMap<String, String> = serde_json::from_slice(&plaintext)
+                .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+            secrets.remove(name).ok_or_else(not_found)
+        }
+        }
+    }
+}
+
+/* Replaces every occurrence of the secrets in a captured log */
+pub fn mask(log: Vec<u8>, secrets: &[String]) -> Vec<u8> {
+    let mut log = log;
+
+    for secret in secrets.iter().map(|x| x.as_bytes()) {
+        if secret.is_empty() || log.len() < secret.len() {
+            continue;
+        }
+
+        let mut out = Vec::with_capacity(log.len());
+        let mut i = 0;
+        while i < log.len() {
+            if log[i ..].starts_with(secret) {
+                out.extend_from_slice(MASK);
+                i += secret.len();
+            } else {
+                out.push(log[i]);
+                i += 1;
+            }
+        }
+        log = out;
+    }
+
+    log
+}
@@ -0,0 +1,93 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use log::{error, info};
+
+use crate::{group::SerializedTaskGroup, watch::{FileEvent, FileTrigger, FileWatcher}};
+
+const PROCESSED: &str = "processed";
+const REJECTED: &str = "rejected";
+/* Appended to the name of a rejected file, for the report of the error */
+const ERROR_SUFFIX: &str = ".error";
+
+/* Directory where dropping a task group, as a .json file, submits it.
+ * The file is then moved to processed/ or rejected/, with the reason in a
+ * .error file next to it. Files must be written elsewhere then moved in,
+ * or written at once.
+ */
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    watcher: FileWatcher,
+    /* Files that were there before the watch started */
+    initial: Vec<PathBuf>,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(dir.join(PROCESSED))?;
+        fs::create_dir_all(dir.join(REJECTED))?;
+
+        let watcher = FileWatcher::new(FileTrigger {
+            path: dir.join("*.json"),
+            events: vec![FileEvent::CloseWrite, FileEvent::Move],
+            debounce: None,
+            stable_for: None,
+        })?;
+
+        let mut initial: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file()
+                && path.extension().is_some_and(|x| x == "json")
+                && !path.file_name().unwrap().to_string_lossy().starts_with('.'))
+            .collect();
+        initial.sort();
+
+        Ok(Self { dir, watcher, initial })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /* Files submitted since the last call */
+    pub fn take_files(&mut self) -> Vec<PathBuf> {
+        let mut out = std::mem::take(&mut self.initial);
+        match self.watcher.poll() {
+        Ok(paths) => for path in paths {
+            if !out.contains(&path) {
+                out.push(path);
+            }
+        },
+        Err(e) => error!("[SPOOL] Unable to read the events of {:?}: {}", self.dir, e)
+        }
+        out
+    }
+
+    pub fn read(path: &Path) -> Result<SerializedTaskGroup, String> {
+        let content = fs::read(path).map_err(|e| e.to_string())?;
+        serde_json::from_slice(&content).map_err(|e| e.to_string())
+    }
+
+    /* Moves the file out of the spool, with the error if it was rejected */
+    pub fn done(&self, path: &Path, result: Result<(), String>) {
+        let name = path.file_name().unwrap();
+        let moved = match &result {
+        Ok(()) => {
+            info!("[SPOOL] Submitted {:?}", path);
+            fs::rename(path, self.dir.join(PROCESSED).join(name))
+        },
+        Err(e) => {
+            error!("[SPOOL] Rejected {:?}: {}", path, e);
+            let mut report = name.to_os_string();
+            report.push(ERROR_SUFFIX);
+            fs::write(self.dir.join(REJECTED).join(report), format!("{}\n", e))
+                .and_then(|_| fs::rename(path, self.dir.join(REJECTED).join(name)))
+        }
+        };
+
+        if let Err(e) = moved {
+            error!("[SPOOL] Unable to move {:?}: {}", path, e);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+fn default_fail_on_signal() -> bool {
+    true
+}
+
+fn is_true(x: &bool) -> bool {
+    *x
+}
+
+/* Regular expression on the output, compiled once when read. An invalid
+ * one is kept with its error, for validate to report it.
+ */
+#[derive(Clone, Debug)]
+pub struct OutputPattern {
+    pattern: String,
+    regex: Result<Regex, String>,
+}
+
+impl OutputPattern {
+    pub fn new(pattern: String) -> Self {
+        let regex = Regex::new(&pattern).map_err(|e| e.to_string());
+        Self { pattern, regex }
+    }
+
+    fn regex(&self) -> Result<&Regex, String> {
+        self.regex.as_ref().map_err(|e| e.clone())
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl Serialize for OutputPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        self.pattern.serialize(serializer)
+    }
+}
+
+/* Decides whether an execution succeeded, by order of precedence:
+ * - fail_if_output_matches, when stdout or stderr matches it
+ * - succeed_if_output_matches, when stdout or stderr matches it
+ * - fail_on_signal, when the process was killed by a signal
+ * - failure_exit_codes, then success_exit_codes (by default, only 0)
+ * Timeouts and out of memory kills are failures whatever these are.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SuccessCriteria {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_exit_codes: Option<Vec<i32>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failure_exit_codes: Vec<i32>,
+    #[serde(default = "default_fail_on_signal", skip_serializing_if = "is_true")]
+    pub fail_on_signal: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fail_if_output_matches: Option<OutputPattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub succeed_if_output_matches: Option<OutputPattern>,
+}
+
+impl Default for SuccessCriteria {
+    fn default() -> Self {
+        Self {
+            success_exit_codes: None,
+            failure_exit_codes: Vec::new(),
+            fail_on_signal: true,
+            fail_if_output_matches: None,
+            succeed_if_output_matches: None,
+        }
+    }
+}
+
+fn regex(pattern: &Option<OutputPattern>) -> Result<Option<&Regex>, String> {
+    pattern.as_ref()
+        .map(OutputPattern::regex)
+        .transpose()
+}
+
+impl SuccessCriteria {
+    pub fn validate(&self) -> Result<(), String> {
+        regex(&self.fail_if_output_matches)?;
+        regex(&self.succeed_if_output_matches)?;
+
+        if let Some(codes) = &self.success_exit_codes
+            && let Some(code) = codes.iter().find(|x| self.failure_exit_codes.contains(x)) {
+            return Err(format!("Exit code {} is both a success and a failure", code));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_success(&self, status: &ExitStatus, stdout: &[u8], stderr: &[u8]) -> Result<bool, String> {
+        let matches = |pattern: Option<&Regex>| pattern
+            .is_some_and(|x| x.is_match(stdout) || x.is_match(stderr));
+
+        if matches(regex(&self.fail_if_output_matches)?) {
+            return Ok(false);
+        }
+        if matches(regex(&self.succeed_if_output_matches)?) {
+            return Ok(true);
+        }
+
+        let code = match status.code() {
+        Some(code) => code,
+        None => return Ok(status.signal().is_some() && !self.fail_on_signal)
+        };
+
+        if self.failure_exit_codes.contains(&code) {
+            return Ok(false);
+        }
+
+        Ok(match &self.success_exit_codes {
+        Some(codes) => codes.contains(&code),
+        None => code == 0
+        })
+    }
+}
@@ -1,40 +1,321 @@
 
 use std::{
-    fmt::{self, Formatter}, fs, path::PathBuf, sync::{Arc, RwLock}, thread::{self, JoinHandle}, time::Duration
+    collections::{BTreeMap, VecDeque}, fmt::{self, Formatter}, fs, io, os::unix::process::ExitStatusExt, path::PathBuf, sync::{Arc, RwLock}, thread::{self, JoinHandle}, time::{Duration, Instant}
 };
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use log::{debug, warn, error};
+use log::{debug, info, warn, error};
 
-use crate::command::*;
+use crate::{cgroup, command::*, env::{self, RunContext}, hooks::{Hook, HookEvent, Hooks, RunDetails}, load::{RunWhen, SystemLoad}, metrics::{QuantileSketch, TaskMetrics}, secrets::SecretStore};
+
+/* Length of the rolling window of the statistics */
+const STATISTIC_WINDOW_HOURS: i64 = 24;
+
+#[derive(Debug)]
+struct WindowEntry {
+    at: DateTime<Utc>,
+    success: bool,
+    duration: Option<Duration>,
+}
 
 #[derive(Debug, Default)]
 pub struct TaskStatistic {
     count: usize,
+    success_count: usize,
+    /* Executions that could not be started or waited for */
     error_count: usize,
-    average_duration: Duration
+    skipped_count: usize,
+    timeout_count: usize,
+    out_of_memory_count: usize,
+    exit_codes: BTreeMap<i32, usize>,
+    signals: BTreeMap<i32, usize>,
+
+    average_duration: Duration,
+    durations: QuantileSketch,
+
+    total_user_time: Duration,
+    total_system_time: Duration,
+    last_max_rss: u64,
+    average_max_rss: f64,
+    peak_max_rss: u64,
+    peak_memory: Option<u64>,
+
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+    failure_streak: usize,
+    /* Of the last execution which ran */
+    last_scheduled_time: Option<DateTime<Utc>>,
+    last_fire_time: Option<DateTime<Utc>>,
+    window: VecDeque<WindowEntry>,
 }
 
-impl fmt::Display for TaskStatistic {
+/* Executions of the rolling window */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowSummary {
+    pub hours: i64,
+    pub count: usize,
+    pub failure_count: usize,
+    /* In seconds */
+    pub average_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+}
+
+/* Snapshot of a TaskStatistic, as sent to the clients. Durations are in
+ * seconds, and timestamps are the end of the executions.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatisticSummary {
+    pub count: usize,
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub error_count: usize,
+    pub skipped_count: usize,
+    pub timeout_count: usize,
+    pub out_of_memory_count: usize,
+    pub exit_codes: BTreeMap<i32, usize>,
+    pub signals: BTreeMap<i32, usize>,
+
+    pub average_duration: Option<f64>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub p50_duration: Option<f64>,
+    pub p95_duration: Option<f64>,
+    pub p99_duration: Option<f64>,
+
+    pub total_user_time: f64,
+    pub total_system_time: f64,
+    pub last_max_rss: u64,
+    pub average_max_rss: f64,
+    pub peak_max_rss: u64,
+    pub peak_memory: Option<u64>,
+
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub failure_streak: usize,
+    /* Nominal and actual start of the last execution which ran */
+    #[serde(default)]
+    pub last_scheduled_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_fire_time: Option<DateTime<Utc>>,
+    pub window: WindowSummary,
+}
+
+impl TaskStatistic {
+    pub fn record(&mut self, res: &TaskOutput, at: DateTime<Utc>) {
+        let success = matches!(res, TaskOutput::NoError(x) if x.is_success());
+        match res {
+        TaskOutput::Waiting => return,
+        TaskOutput::IOError(_) |
+        TaskOutput::PoisonError => self.error_count += 1,
+        TaskOutput::TooManyThreadsError => self.skipped_count += 1,
+        TaskOutput::TimedOut(_) => self.timeout_count += 1,
+        TaskOutput::OutOfMemory(_) => self.out_of_memory_count += 1,
+        TaskOutput::NoError(_) => {}
+        }
+
+        self.count += 1;
+        if success {
+            self.success_count += 1;
+            self.last_success = Some(at);
+            self.failure_streak = 0;
+        } else {
+            self.last_failure = Some(at);
+            self.failure_streak += 1;
+        }
+
+        let outcome = res.outcome();
+        if let Some(outcome) = outcome {
+            self.last_scheduled_time = Some(outcome.scheduled_time);
+            self.last_fire_time = Some(outcome.fire_time);
+            if let Some(code) = outcome.exit_status.code() {
+                *self.exit_codes.entry(code).or_insert(0) += 1;
+            }
+            if let Some(signal) = outcome.exit_status.signal() {
+                *self.signals.entry(signal).or_insert(0) += 1;
+            }
+
+            let n = self.durations.count() as f64;
+            self.average_duration =
+                self.average_duration.mul_f64(n / (n + 1.)) +
+                outcome.duration.div_f64(n + 1.);
+            self.durations.insert(outcome.duration.as_secs_f64());
+
+            let usage = &outcome.usage;
+            self.total_user_time += usage.user_time;
+            self.total_system_time += usage.system_time;
+            self.last_max_rss = usage.max_rss;
+            self.average_max_rss =
+                self.average_max_rss * n / (n + 1.) +
+                (usage.max_rss as f64) / (n + 1.);
+            self.peak_max_rss = self.peak_max_rss.max(usage.max_rss);
+            if let Some(peak) = usage.memory_peak {
+                self.peak_memory = Some(
+                    self.peak_memory.map_or(peak, |x| x.max(peak))
+                );
+            }
+        }
+
+        self.window.push_back(WindowEntry {
+            at,
+            success,
+            duration: outcome.map(|x| x.duration),
+        });
+        let cutoff = at - chrono::Duration::hours(STATISTIC_WINDOW_HOURS);
+        while self.window.front().is_some_and(|x| x.at < cutoff) {
+            self.window.pop_front();
+        }
+    }
+
+    pub fn summary(&self) -> StatisticSummary {
+        self.summary_at(Utc::now())
+    }
+
+    pub fn summary_at(&self, now: DateTime<Utc>) -> StatisticSummary {
+        let cutoff = now - chrono::Duration::hours(STATISTIC_WINDOW_HOURS);
+        let window: Vec<&WindowEntry> = self.window.iter()
+            .filter(|x| x.at >= cutoff)
+            .collect();
+        let window_durations: Vec<f64> = window.iter()
+            .filter_map(|x| x.duration)
+            .map(|x| x.as_secs_f64())
+            .collect();
+
+        StatisticSummary {
+            count: self.count,
+            success_count: self.success_count,
+            failure_count: self.count - self.success_count,
+            error_count: self.error_count,
+            skipped_count: self.skipped_count,
+            timeout_count: self.timeout_count,
+            out_of_memory_count: self.out_of_memory_count,
+            exit_codes: self.exit_codes.clone(),
+            signals: self.signals.clone(),
+
+            average_duration: (self.durations.count() > 0)
+                .then_some(self.average_duration.as_secs_f64()),
+            min_duration: self.durations.min(),
+            max_duration: self.durations.max(),
+            p50_duration: self.durations.quantile(0.5),
+            p95_duration: self.durations.quantile(0.95),
+            p99_duration: self.durations.quantile(0.99),
+
+            total_user_time: self.total_user_time.as_secs_f64(),
+            total_system_time: self.total_system_time.as_secs_f64(),
+            last_max_rss: self.last_max_rss,
+            average_max_rss: self.average_max_rss,
+            peak_max_rss: self.peak_max_rss,
+            peak_memory: self.peak_memory,
+
+            last_success: self.last_success,
+            last_failure: self.last_failure,
+            failure_streak: self.failure_streak,
+            last_scheduled_time: self.last_scheduled_time,
+            last_fire_time: self.last_fire_time,
+            window: WindowSummary {
+                hours: STATISTIC_WINDOW_HOURS,
+                count: window.len(),
+                failure_count: window.iter().filter(|x| !x.success).count(),
+                average_duration: (!window_durations.is_empty()).then(||
+                    window_durations.iter().sum::<f64>() / window_durations.len() as f64
+                ),
+                max_duration: window_durations.iter().copied().reduce(f64::max),
+            },
+        }
+    }
+}
+
+fn rate(part: usize, total: usize) -> String {
+    if total == 0 {
+        String::from("n/a")
+    } else {
+        format!("{:.1}%", 100. * (part as f64) / (total as f64))
+    }
+}
+
+fn seconds(value: Option<f64>) -> String {
+    value.map_or(String::from("n/a"), |x| format!("{:.3}s", x))
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value.map_or(String::from("never"), |x| x.to_rfc3339())
+}
+
+impl fmt::Display for StatisticSummary {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         writeln!(fmt, "=== Statistics ===")?;
-        writeln!(fmt, "Execution count: {}", self.count)?;
-        writeln!(fmt, "Error rate: {}%", 100. * (self.error_count as f64) / (self.count as f64))?;
-        write!(fmt, "Average execution time: {:?}", self.average_duration)?;
-        
-        Ok(())
+        writeln!(fmt, "Execution count: {} ({} succeeded, {} failed)",
+            self.count, self.success_count, self.failure_count)?;
+        writeln!(fmt, "Error rate: {}", rate(self.failure_count, self.count))?;
+        writeln!(fmt, "Not started: {} errors, {} skipped",
+            self.error_count, self.skipped_count)?;
+        writeln!(fmt, "Killed: {} timeouts, {} out of memory",
+            self.timeout_count, self.out_of_memory_count)?;
+        let codes: Vec<String> = self.exit_codes.iter()
+            .map(|(code, n)| format!("{}: {}", code, n))
+            .collect();
+        writeln!(fmt, "Exit codes: {}", codes.join(", "))?;
+        let signals: Vec<String> = self.signals.iter()
+            .map(|(signal, n)| format!("{}: {}", signal, n))
+            .collect();
+        writeln!(fmt, "Signals: {}", signals.join(", "))?;
+        writeln!(fmt, "Execution time: {} average, {} min, {} max",
+            seconds(self.average_duration), seconds(self.min_duration), seconds(self.max_duration))?;
+        writeln!(fmt, "Execution time percentiles: {} p50, {} p95, {} p99",
+            seconds(self.p50_duration), seconds(self.p95_duration), seconds(self.p99_duration))?;
+        writeln!(fmt, "Total CPU time: {:.3}s user, {:.3}s system",
+            self.total_user_time, self.total_system_time)?;
+        writeln!(fmt, "Max RSS: {} KiB last, {:.0} KiB average, {} KiB peak",
+            self.last_max_rss, self.average_max_rss, self.peak_max_rss)?;
+        if let Some(peak) = self.peak_memory {
+            writeln!(fmt, "Cgroup memory peak: {} bytes", peak)?;
+        }
+        writeln!(fmt, "Last success: {}", timestamp(self.last_success))?;
+        writeln!(fmt, "Last failure: {}", timestamp(self.last_failure))?;
+        writeln!(fmt, "Consecutive failures: {}", self.failure_streak)?;
+        if let (Some(scheduled), Some(fired)) = (self.last_scheduled_time, self.last_fire_time) {
+            writeln!(fmt, "Last execution: scheduled at {}, fired at {}",
+                scheduled.to_rfc3339(), fired.to_rfc3339())?;
+        }
+        write!(fmt, "Last {}h: {} executions, error rate {}, {} average, {} max",
+            self.window.hours, self.window.count,
+            rate(self.window.failure_count, self.window.count),
+            seconds(self.window.average_duration), seconds(self.window.max_duration))
+    }
+}
+
+impl fmt::Display for TaskStatistic {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.summary().fmt(fmt)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskConfig {
+    pub name: Option<String>,
     pub cmd: Command,
     pub max_concurrent_execution: Option<usize>,
+    /* Tasks of the group, by name or index, that must have succeeded in
+     * the same group run before this one starts */
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /* Number of times a failed execution is retried */
+    pub retries: Option<u32>,
+    /* In seconds */
+    pub retry_delay: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_when: Option<RunWhen>,
+    #[serde(flatten)]
+    pub hooks: Hooks,
 
     #[serde(skip_deserializing)]
     pub stdout_path: Option<PathBuf>,
     #[serde(skip_deserializing)]
     pub stderr_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub cgroup_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub secrets: Option<SecretStore>,
 }
 
 #[derive(Debug)]
@@ -42,14 +323,25 @@ pub struct Task {
     config: Arc<RwLock<TaskConfig>>,
 
     executions: Vec<TaskOutput>,
-    running_threads: Vec<(usize, JoinHandle<TaskOutput>)>,
+    /* Number of the first execution, in the names of the logs and cgroups,
+     * which go on from those of the task replaced */
+    first_execution: usize,
+    running_threads: Vec<(usize, RunContext, JoinHandle<TaskOutput>)>,
+    pending_retries: Vec<(Instant, RunContext)>,
+    /* Executions waiting for the system to be idle, until their deadline */
+    waiting_for_idle: Vec<(Option<Instant>, RunContext)>,
+    events: Vec<(HookEvent, RunDetails)>,
+    /* Group runs whose last attempt is over: run id, execution, success */
+    finished_runs: Vec<(u64, usize, bool)>,
     stats: TaskStatistic,
+    metrics: TaskMetrics,
+    last_success: Option<DateTime<Utc>>,
 }
 
 impl Task {
     pub fn new(conf: TaskConfig) -> Self {
         let running_threads = {
-            if let Some(max) = (&conf).max_concurrent_execution {
+            if let Some(max) = conf.max_concurrent_execution {
                 Vec::with_capacity(max)
             } else {
                 Vec::new()
@@ -59,8 +351,15 @@ impl Task {
         Self {
             config: Arc::new(RwLock::new(conf)),
             executions: Vec::new(),
-            running_threads: running_threads,
+            first_execution: 0,
+            running_threads,
+            pending_retries: Vec::new(),
+            waiting_for_idle: Vec::new(),
+            events: Vec::new(),
+            finished_runs: Vec::new(),
             stats: TaskStatistic::default(),
+            metrics: TaskMetrics::default(),
+            last_success: None,
         }
     }
 
@@ -87,58 +386,182 @@ impl Task {
         conf.stderr_path = Some(stderr_path);
     }
     
+    pub fn set_cgroup_path(&mut self, path: PathBuf) {
+        if let Err(e) = cgroup::create_parent(&path) {
+            error!("Unable to set up the cgroup {:?}: {}", path, e);
+            return;
+        }
+
+        self.config.write().unwrap().cgroup_path = Some(path);
+    }
+
+    pub fn set_secret_store(&mut self, store: SecretStore) {
+        self.config.write().unwrap().secrets = Some(store);
+    }
+
+    pub fn secrets(&self) -> Option<SecretStore> {
+        self.config.read().unwrap().secrets.clone()
+    }
+
+    /* Numbers the executions after those of the task replaced, which may
+     * still be running in the same directories */
+    pub fn continue_from(&mut self, other: &Task) {
+        self.first_execution = other.first_execution + other.executions.len();
+    }
+
     fn update_log(&self, idx: usize, output: TaskOutput) -> TaskOutput {
-        let mut res = output?;
+        match output {
+        TaskOutput::NoError(res) =>
+            TaskOutput::NoError(self.write_logs(idx, res)?),
+        TaskOutput::TimedOut(res) =>
+            TaskOutput::TimedOut(self.write_logs(idx, res)?),
+        TaskOutput::OutOfMemory(res) =>
+            TaskOutput::OutOfMemory(self.write_logs(idx, res)?),
+        output => output
+        }
+    }
 
-        if let Some(path) = &self.config.read()?.stdout_path {
-            if let Log::Buffer(log) = &res.stdout {
-                let path = path.join(idx.to_string());
-                fs::write(&path, log)?;
-                res.stdout = Log::File(path);
-            }
+    fn write_logs(&self, idx: usize, mut res: CommandOutcome) -> TaskOutput {
+        if let Some(path) = &self.config.read()?.stdout_path
+            && let Log::Buffer(log) = &res.stdout {
+            let path = path.join((self.first_execution + idx).to_string());
+            fs::write(&path, log)?;
+            res.stdout = Log::File(path);
         }
 
-        if let Some(path) = &self.config.read()?.stderr_path {
-            if let Log::Buffer(log) = &res.stderr {
-                let path = path.join(idx.to_string());
-                fs::write(&path, log)?;
-                res.stderr = Log::File(path);
-            }
+        if let Some(path) = &self.config.read()?.stderr_path
+            && let Log::Buffer(log) = &res.stderr {
+            let path = path.join((self.first_execution + idx).to_string());
+            fs::write(&path, log)?;
+            res.stderr = Log::File(path);
         }
 
         TaskOutput::NoError(res)
     }
 
-    fn update_stats(&mut self, res: &TaskOutput) {
-        if let TaskOutput::NoError(outcome) = res {
-            let n = self.stats.count - self.stats.error_count;
-            let n: u32 = n.try_into().unwrap();
-            let n: f64 = n.try_into().unwrap();
-            
-            self.stats.average_duration =
-                self.stats.average_duration.mul_f64(n / (n + 1.)) +
-                outcome.duration.div_f64(n + 1.);
+    fn join(&mut self, handler: JoinHandle<TaskOutput>) -> TaskOutput {
+        TaskOutput::NoError(handler.join().unwrap()?)
+    }
+
+    /* Schedules a retry if the execution failed and it is allowed to,
+     * and records the events the hooks are fired for.
+     */
+    fn handle_outcome(&mut self, idx: usize, ctx: &RunContext, output: &TaskOutput) {
+        let success = matches!(output, TaskOutput::NoError(x) if x.is_success());
+        if success {
+            self.last_success = self.last_success.max(Some(ctx.scheduled_time));
+            self.events.push((HookEvent::Success, RunDetails::new(HookEvent::Success, ctx, output)));
+            self.finished_runs.push((ctx.run_id, idx, true));
+            return;
+        }
+
+        self.events.push((HookEvent::Failure, RunDetails::new(HookEvent::Failure, ctx, output)));
+
+        let (retries, delay) = {
+            let conf = self.config.read().unwrap();
+            (conf.retries.unwrap_or(0), conf.retry_delay.unwrap_or(0))
+        };
+        if ctx.attempt <= retries {
+            info!("\"{}\": Retrying \"{}\" in {}s", ctx.group, ctx.task, delay);
+            let mut ctx = ctx.clone();
+            ctx.attempt += 1;
+            self.pending_retries.push((Instant::now() + Duration::from_secs(delay), ctx));
         } else {
-            self.stats.error_count += 1;
+            /* Without retries, the failure says it all */
+            if retries > 0 {
+                self.events.push((HookEvent::RetryExhausted, RunDetails::new(HookEvent::RetryExhausted, ctx, output)));
+            }
+            self.finished_runs.push((ctx.run_id, idx, false));
         }
+    }
 
-        self.stats.count += 1;
+    fn set_task_output(&mut self, idx: usize, ctx: &RunContext, output: TaskOutput) {
+        debug!("Execution n°{} is over", idx);
+        if let Some(hash) = output.outcome().and_then(|x| x.script_hash.as_ref()) {
+            info!("\"{}\": Execution n°{} ran the script sha256:{}", ctx.task, idx, hash);
+        }
+
+        self.stats.record(&output, Utc::now());
+        self.metrics.record(&output);
+        let output = self.update_log(idx, output);
+        self.handle_outcome(idx, ctx, &output);
+        self.executions[idx] = output;
     }
 
-    fn join(&mut self, handler: JoinHandle<TaskOutput>) -> TaskOutput {
-        TaskOutput::NoError(handler.join().unwrap()?)
+    /* Events that happened since the last call */
+    pub fn take_events(&mut self) -> Vec<(HookEvent, RunDetails)> {
+        std::mem::take(&mut self.events)
     }
 
-    fn set_task_output(&mut self, idx: usize, output: TaskOutput) {
-        debug!("Execution n°{} is over", idx);
+    /* Group runs that are over since the last call */
+    pub fn take_finished_runs(&mut self) -> Vec<(u64, usize, bool)> {
+        std::mem::take(&mut self.finished_runs)
+    }
+
+    /* Stdout of an execution, read back from its log file if needed */
+    pub fn stdout(&self, idx: usize) -> io::Result<Vec<u8>> {
+        match self.executions.get(idx).and_then(|x| x.outcome()).map(|x| &x.stdout) {
+        Some(Log::Buffer(x)) => Ok(x.clone()),
+        Some(Log::File(path)) => fs::read(path),
+        Some(Log::Nothing) => Ok(Vec::new()),
+        Some(Log::Missing) |
+        None => Err(io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
+
+    /* Outputs written by an execution */
+    pub fn outputs(&self, idx: usize) -> BTreeMap<String, String> {
+        self.executions.get(idx)
+            .and_then(|x| x.outcome())
+            .map(|x| x.outputs.clone())
+            .unwrap_or_default()
+    }
+
+    /* Tasks to wait for, including the one the stdin is read from */
+    pub fn dependencies(&self) -> Vec<String> {
+        let mut out = self.config.read().unwrap().depends_on.clone();
+        if let Some(name) = self.stdin_task()
+            && !out.contains(&name) {
+            out.push(name);
+        }
+        out
+    }
 
-        self.update_stats(&output);
-        self.executions[idx] = self.update_log(idx, output);
+    pub fn stdin_task(&self) -> Option<String> {
+        match &self.config.read().unwrap().cmd.stdin {
+        Some(Stdin::Task(name)) => Some(name.clone()),
+        _ => None
+        }
     }
 
-    pub fn run(&mut self) {
+    pub fn hooks(&self, event: HookEvent) -> Vec<Hook> {
+        self.config.read().unwrap().hooks.get(event).to_vec()
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.config.read().unwrap().name.clone()
+    }
+
+    /* Executions wait for the system to be idle enough, if the task asks
+     * for it */
+    pub fn run(&mut self, ctx: RunContext) {
+        let run_when = self.config.read().unwrap().run_when.clone();
+        match run_when {
+        Some(run_when) if !run_when.is_met(&SystemLoad::read()) => {
+            info!("\"{}\": Waiting for the system to be idle to run \"{}\"", ctx.group, ctx.task);
+            let deadline = run_when.max_delay
+                .map(|x| Instant::now() + Duration::from_secs(x));
+            self.waiting_for_idle.push((deadline, ctx));
+        },
+        _ => self.start(ctx)
+        }
+    }
+
+    fn start(&mut self, mut ctx: RunContext) {
         let conf = self.config.clone();
         let idx = self.executions.len();
+        let number = self.first_execution + idx;
+        ctx.prev_success_time = self.last_success;
         
         debug!("Starting execution n°{}", idx);
         self.executions.push(TaskOutput::Waiting);
@@ -146,7 +569,7 @@ impl Task {
         if let Some(max) = conf.read().unwrap().max_concurrent_execution {
             let nb_concurrent_threads = self.running_threads.len();
             if nb_concurrent_threads >= max {
-                self.set_task_output(idx, TaskOutput::TooManyThreadsError);
+                self.set_task_output(idx, &ctx, TaskOutput::TooManyThreadsError);
                 error!("Can't start execution n° {}: Too many concurrent threads", idx);
                 return;
             } else if nb_concurrent_threads == 9 * max / 10 {
@@ -154,10 +577,33 @@ impl Task {
             }
         }
 
-        self.running_threads.push((idx,
+        ctx.fire_time = Utc::now();
+        let thread_ctx = ctx.clone();
+        self.running_threads.push((idx, ctx,
             thread::spawn(
                 move || -> TaskOutput {
-                    conf.read()?.cmd.run()
+                    let mut ctx = thread_ctx;
+                    let conf = conf.read()?;
+                    ctx.cgroup = conf.cgroup_path.as_ref()
+                        .map(|path| path.join(number.to_string()));
+                    ctx.secrets = conf.secrets.clone();
+
+                    /* What a previous attempt wrote is discarded */
+                    if let Some(path) = &ctx.outputs_file {
+                        let _ = fs::remove_file(path);
+                    }
+                    let mut output = conf.cmd.run(&ctx);
+                    if let Some(path) = &ctx.outputs_file
+                        && let Some(outcome) = output.outcome_mut() {
+                        let outputs = conf.cmd.uid()
+                            .map_err(|e| e.to_string())
+                            .and_then(|uid| env::read_outputs(path, uid));
+                        match outputs {
+                        Ok(outputs) => outcome.outputs = outputs,
+                        Err(e) => warn!("\"{}\": Invalid outputs in {:?}: {}", ctx.task, path, e)
+                        }
+                    }
+                    output
                 }
             )
         ));
@@ -169,10 +615,10 @@ impl Task {
         let mut i = 0;
 
         while i < n {
-            if self.running_threads[i].1.is_finished() {
-                let (idx, handler) = self.running_threads.swap_remove(i);
+            if self.running_threads[i].2.is_finished() {
+                let (idx, ctx, handler) = self.running_threads.swap_remove(i);
                 let res = self.join(handler);
-                self.set_task_output(idx, res);
+                self.set_task_output(idx, &ctx, res);
                 n -= 1;
                 has_thread_finished = true;
             } else {
@@ -180,6 +626,35 @@ impl Task {
             }
         }
 
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.pending_retries)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.pending_retries = pending;
+        for (_, ctx) in due {
+            self.run(ctx);
+            has_thread_finished = true;
+        }
+
+        /* Once idle, one execution is started at a time, to let the load
+         * reflect it */
+        if !self.waiting_for_idle.is_empty() {
+            let run_when = self.config.read().unwrap().run_when.clone().unwrap_or_default();
+            let mut is_idle = run_when.is_met(&SystemLoad::read());
+            for (deadline, ctx) in std::mem::take(&mut self.waiting_for_idle) {
+                if is_idle {
+                    is_idle = false;
+                } else if deadline.is_some_and(|x| x <= now) {
+                    warn!("\"{}\": Running \"{}\" after waiting for too long", ctx.group, ctx.task);
+                } else {
+                    self.waiting_for_idle.push((deadline, ctx));
+                    continue;
+                }
+                self.start(ctx);
+                has_thread_finished = true;
+            }
+        }
+
         has_thread_finished
     }
 
@@ -194,4 +669,21 @@ impl Task {
     pub fn stats(&self) -> &TaskStatistic {
         &self.stats
     }
+
+    pub fn metrics(&self) -> &TaskMetrics {
+        &self.metrics
+    }
+
+    /* Scheduled time of the last successful execution */
+    pub fn last_success(&self) -> Option<DateTime<Utc>> {
+        self.last_success
+    }
+
+    pub fn nb_pending_retries(&self) -> usize {
+        self.pending_retries.len()
+    }
+
+    pub fn nb_waiting_for_idle(&self) -> usize {
+        self.waiting_for_idle.len()
+    }
 }
\ No newline at end of file
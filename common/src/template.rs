@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use chrono::{format::{Item, StrftimeItems}, DateTime, Utc};
+
+/* Placeholders are written {{name}} or {{name:format}}, the format being
+ * a strftime-like format, only valid for dates. A literal "{{", like in
+ * `docker ps --format '{{.Names}}'`, is written "{{{{".
+ */
+#[derive(Debug, Clone)]
+pub enum Value {
+    Text(String),
+    Time(Option<DateTime<Utc>>),
+}
+
+pub type Variables = HashMap<String, Value>;
+
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str, Option<&'a str>),
+}
+
+fn walk<'a>(template: &'a str, mut f: impl FnMut(Piece<'a>) -> Result<(), String>)
+    -> Result<(), String> {
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[start ..].starts_with("{{{{") {
+            f(Piece::Text(&rest[.. start + 2]))?;
+            rest = &rest[start + 4 ..];
+            continue;
+        }
+
+        f(Piece::Text(&rest[.. start]))?;
+        let after = &rest[start + 2 ..];
+        let end = after.find("}}")
+            .ok_or_else(|| format!("Unclosed placeholder in \"{}\"", template))?;
+
+        let inner = after[.. end].trim();
+        f(match inner.split_once(':') {
+        Some((name, format)) => Piece::Placeholder(name.trim(), Some(format)),
+        None => Piece::Placeholder(inner, None)
+        })?;
+        rest = &after[end + 2 ..];
+    }
+
+    f(Piece::Text(rest))
+}
+
+fn check_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|x| matches!(x, Item::Error)) {
+        Err(format!("Invalid date format: {}", format))
+    } else {
+        Ok(())
+    }
+}
+
+/* A "prefix.*" variable stands for any name starting with "prefix." */
+fn lookup<'a>(vars: &'a Variables, name: &str) -> Option<&'a Value> {
+    vars.get(name).or_else(|| {
+        let (prefix, _) = name.split_once('.')?;
+        vars.get(&format!("{}.*", prefix))
+    })
+}
+
+fn check(vars: &Variables, name: &str, format: Option<&str>) -> Result<(), String> {
+    match (lookup(vars, name), format) {
+    (None, _) => Err(format!("Unknown placeholder: {} (a literal \"{{{{\" is written \"{{{{{{{{\")", name)),
+    (Some(Value::Text(_)), Some(_)) =>
+        Err(format!("{} doesn't take a format", name)),
+    (Some(Value::Time(_)), Some(format)) => check_format(format),
+    _ => Ok(())
+    }
+}
+
+/* Unset dates are rendered as an empty string */
+pub fn render(template: &str, vars: &Variables) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+
+    walk(template, |piece| {
+        match piece {
+        Piece::Text(x) => out.push_str(x),
+        Piece::Placeholder(name, format) => {
+            check(vars, name, format)?;
+            match (lookup(vars, name).unwrap(), format) {
+            (Value::Text(x), _) => out.push_str(x),
+            (Value::Time(None), _) => {},
+            (Value::Time(Some(x)), None) => out.push_str(&x.to_rfc3339()),
+            (Value::Time(Some(x)), Some(format)) =>
+                out.push_str(&x.format(format).to_string())
+            }
+        }
+        }
+        Ok(())
+    })?;
+
+    Ok(out)
+}
+
+/* Checks a template against the kind of each known variable, regardless
+ * of their values.
+ */
+pub fn validate(template: &str, vars: &Variables) -> Result<(), String> {
+    walk(template, |piece| {
+        match piece {
+        Piece::Text(_) => Ok(()),
+        Piece::Placeholder(name, format) => check(vars, name, format)
+        }
+    })
+}
@@ -0,0 +1,159 @@
+use std::{ffi::{CStr, CString, OsString}, io, path::PathBuf};
+
+/* Identity the spawned process switches to. The switch is done by hand
+ * rather than through CommandExt::uid/gid, since std drops the privileges
+ * before running the pre_exec hooks, and the sandbox needs them.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub groups: Option<Vec<libc::gid_t>>,
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl Credentials {
+    /* Meant to be called from a pre_exec hook. The supplementary groups
+     * are resolved beforehand, since initgroups(3) isn't async-signal-safe.
+     */
+    pub fn apply(&self) -> io::Result<()> {
+        /* Like std, the supplementary groups are only changed as root, since
+         * anyone else would get EPERM even when keeping their own groups */
+        if unsafe { libc::getuid() } == 0 {
+            if let Some(groups) = &self.groups {
+                check(unsafe { libc::setgroups(groups.len(), groups.as_ptr()) })?;
+            } else if self.uid.is_some() {
+                check(unsafe { libc::setgroups(0, std::ptr::null()) })?;
+            }
+        }
+
+        if let Some(gid) = self.gid {
+            check(unsafe { libc::setgid(gid) })?;
+        }
+
+        if let Some(uid) = self.uid {
+            check(unsafe { libc::setuid(uid) })?;
+        }
+
+        Ok(())
+    }
+}
+
+/* An entry of the user database, as returned by NSS */
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid name: {}", name))
+}
+
+/* Calls one of the reentrant NSS getters, growing the buffer as needed.
+ * The entry points into the buffer, so it is converted before returning.
+ */
+fn nss_lookup<T, R>(
+    mut lookup: impl FnMut(&mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+    convert: impl FnOnce(&T) -> R
+) -> io::Result<Option<R>> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry: T = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+
+        match lookup(&mut entry, &mut buffer, &mut result) {
+        0 if result.is_null() => return Ok(None),
+        0 => return Ok(Some(convert(&entry))),
+        libc::ERANGE => {
+            let n = buffer.len();
+            buffer.resize(2 * n, 0);
+        },
+        e => return Err(io::Error::from_raw_os_error(e))
+        }
+    }
+}
+
+impl User {
+    unsafe fn from_passwd(pw: &libc::passwd) -> Self {
+        let to_string = |x: *const libc::c_char| unsafe {
+            CStr::from_ptr(x).to_string_lossy().into_owned()
+        };
+
+        Self {
+            name: to_string(pw.pw_name),
+            uid: pw.pw_uid,
+            gid: pw.pw_gid,
+            home: PathBuf::from(to_string(pw.pw_dir)),
+            shell: PathBuf::from(to_string(pw.pw_shell)),
+        }
+    }
+
+    pub fn from_name(name: &str) -> io::Result<Option<Self>> {
+        let cname = CString::new(name).map_err(|_| invalid_name(name))?;
+        nss_lookup(
+            |pw, buf, result| unsafe {
+                libc::getpwnam_r(cname.as_ptr(), pw, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |pw| unsafe { Self::from_passwd(pw) }
+        )
+    }
+
+    pub fn from_uid(uid: u32) -> io::Result<Option<Self>> {
+        nss_lookup(
+            |pw, buf, result| unsafe {
+                libc::getpwuid_r(uid, pw, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |pw| unsafe { Self::from_passwd(pw) }
+        )
+    }
+
+    /* Variables a login would set */
+    pub fn login_environment(&self) -> [(&'static str, OsString); 4] {
+        [
+            ("HOME", self.home.clone().into()),
+            ("USER", self.name.clone().into()),
+            ("LOGNAME", self.name.clone().into()),
+            ("SHELL", self.shell.clone().into()),
+        ]
+    }
+
+    /* Groups the user belongs to, like initgroups(3) would set them */
+    pub fn groups(&self, gid: u32) -> io::Result<Vec<libc::gid_t>> {
+        let cname = CString::new(self.name.as_bytes())
+            .map_err(|_| invalid_name(&self.name))?;
+        let mut groups: Vec<libc::gid_t> = vec![0; 32];
+
+        loop {
+            let mut n = groups.len() as libc::c_int;
+            let ret = unsafe {
+                libc::getgrouplist(cname.as_ptr(), gid, groups.as_mut_ptr(), &mut n)
+            };
+            if ret >= 0 {
+                groups.truncate(n as usize);
+                return Ok(groups);
+            }
+            groups.resize((n as usize).max(2 * groups.len()), 0);
+        }
+    }
+}
+
+pub fn group_id(name: &str) -> io::Result<Option<u32>> {
+    let cname = CString::new(name.as_bytes()).map_err(|_| invalid_name(name))?;
+    nss_lookup(
+        |gr: &mut libc::group, buf, result| unsafe {
+            libc::getgrnam_r(cname.as_ptr(), gr, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |gr| gr.gr_gid
+    )
+}
@@ -1,3 +1,5 @@
+use std::fmt::{self, Formatter};
+
 use chrono::{DateTime, Datelike, Duration, Months, TimeZone, Timelike, Utc};
 
 macro_rules! check_char {
@@ -37,6 +39,11 @@ pub fn get_start_timestamp_from_string(time: &str) -> Option<DateTime<Utc>> {
         }};
     }
 
+    /* The fields are then sliced by bytes */
+    if !time.is_ascii() || time.len() < 20 {
+        return None;
+    }
+
     let now = chrono::Utc::now();
     
     let year = get_value!(time[0 .. 4], now.year());
@@ -51,7 +58,7 @@ pub fn get_start_timestamp_from_string(time: &str) -> Option<DateTime<Utc>> {
     check_char!(time[16], ':');
     let sec = get_value!(time[17 .. 19], now.second());
 
-    let date = Utc.with_ymd_and_hms(year, month, day, hour, min, sec).unwrap();
+    let date = Utc.with_ymd_and_hms(year, month, day, hour, min, sec).single()?;
 
     let tz_shift = {
         let c = time.chars().nth(19)?;
@@ -61,8 +68,8 @@ pub fn get_start_timestamp_from_string(time: &str) -> Option<DateTime<Utc>> {
             let tz = &time[20 ..];
             let (h, m) =
                 match tz.find(':') {
-                Some(idx) if tz.len() == 5  =>
-                    (&tz[0 .. idx], &tz[idx+1 .. idx+3]),
+                Some(2) if tz.len() == 5  =>
+                    (&tz[0 .. 2], &tz[3 .. 5]),
                 None if tz.len() == 4 =>
                     (&tz[0 .. 2], &tz[2 .. 4]),
                 _ => return None
@@ -81,63 +88,300 @@ pub fn get_start_timestamp_from_string(time: &str) -> Option<DateTime<Utc>> {
         }
     };
 
-    Some(date + tz_shift)
+    date.checked_add_signed(tz_shift)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, PartialEq)]
 pub struct YmdHmsDuration {
     year: u32,
     month: u32,
+    week: i64,
     day: i64,
     hour: i64,
     min: i64,
     sec: i64,
+    nanos: i64,
 }
 
 impl YmdHmsDuration {
-    pub fn add(&self, other: DateTime<Utc>) -> DateTime<Utc> {
-        other +
-            Months::new(12 * self.year + self.month) +
-            Duration::seconds(self.sec) +
-            Duration::minutes(self.min) +
-            Duration::hours(self.hour) +
-            Duration::days(self.day)
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /* The lengths are computed on 128 bits, and refused if they overflow
+     * all the same */
+    fn months(&self) -> Option<i128> {
+        (self.year as i128).checked_mul(12)?
+            .checked_add(self.month as i128)
+    }
+
+    /* Length of the part of the period not counted in months */
+    fn fixed_nanos(&self) -> Option<i128> {
+        let secs = [(self.sec, 1), (self.min, 60), (self.hour, 3600), (self.day, 86_400), (self.week, 604_800)]
+            .into_iter()
+            .try_fold(0i128, |acc, (value, unit)| acc.checked_add((value as i128).checked_mul(unit)?))?;
+        secs.checked_mul(1_000_000_000)?
+            .checked_add(self.nanos as i128)
+    }
+
+    /* Occurrence n°k of the schedule starting at the anchor. Months are
+     * added to the anchor all at once, a day missing from the month
+     * giving its last day: from January 31st, monthly occurrences are on
+     * February 28th (or 29th), March 31st, April 30th...
+     */
+    pub fn nth(&self, anchor: DateTime<Utc>, k: u64) -> Option<DateTime<Utc>> {
+        let months = u32::try_from(self.months()?.checked_mul(k as i128)?).ok()?;
+        let fixed = self.fixed_nanos()?.checked_mul(k as i128)?;
+        let fixed = Duration::try_seconds(i64::try_from(fixed / 1_000_000_000).ok()?)?
+            + Duration::nanoseconds((fixed % 1_000_000_000) as i64);
+
+        anchor.checked_add_months(Months::new(months))?
+            .checked_add_signed(fixed)
+    }
+
+    /* First occurrence of the schedule starting at the anchor that is not
+     * before the given time */
+    pub fn occurrence_from(&self, anchor: DateTime<Utc>, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if time <= anchor || self.is_zero() {
+            return Some(anchor);
+        }
+
+        /* Closed form for fixed-length periods, otherwise an estimate
+         * from the average length of a month, corrected by a few steps */
+        const AVERAGE_MONTH: i128 = 2_629_746_000_000_000;
+        let elapsed = (time - anchor).num_nanoseconds()
+            .map(|x| x as i128)
+            .unwrap_or((time - anchor).num_seconds() as i128 * 1_000_000_000);
+        let months = self.months()?;
+        let length = months.checked_mul(AVERAGE_MONTH)?
+            .checked_add(self.fixed_nanos()?)?;
+        let k = if months == 0 {
+            (elapsed + length - 1) / length
+        } else {
+            elapsed / length
+        };
+        let mut k = u64::try_from(k.max(0)).ok()?;
+
+        while self.nth(anchor, k)? < time {
+            k += 1;
+        }
+        while k > 0 && self.nth(anchor, k - 1)? >= time {
+            k -= 1;
+        }
+        self.nth(anchor, k)
     }
 }
 
-impl ToString for YmdHmsDuration {
-    fn to_string(&self) -> std::string::String {
-        format!( "{}-{}-{} {}:{}:{}",
-            self.year, self.month, self.day,
-            self.hour, self.min,   self.sec
-        )
+/* In the ISO 8601 format */
+impl fmt::Display for YmdHmsDuration {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(fmt, "PT0S");
+        }
+
+        write!(fmt, "P")?;
+        for (value, unit) in [(self.year as i64, 'Y'), (self.month as i64, 'M'), (self.week, 'W'), (self.day, 'D')] {
+            if value != 0 {
+                write!(fmt, "{}{}", value, unit)?;
+            }
+        }
+
+        if self.hour != 0 || self.min != 0 || self.sec != 0 || self.nanos != 0 {
+            write!(fmt, "T")?;
+            if self.hour != 0 {
+                write!(fmt, "{}H", self.hour)?;
+            }
+            if self.min != 0 {
+                write!(fmt, "{}M", self.min)?;
+            }
+            if self.nanos != 0 {
+                let fraction = format!("{:09}", self.nanos);
+                write!(fmt, "{}.{}S", self.sec, fraction.trim_end_matches('0'))?;
+            } else if self.sec != 0 {
+                write!(fmt, "{}S", self.sec)?;
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn get_period_from_string(time: &str) -> Option<YmdHmsDuration> {
-    if time.len() != 19 {
+/* Seconds with an optional fraction, down to the nanosecond */
+fn parse_seconds(value: &str) -> Option<(i64, i64)> {
+    let (sec, fraction) = value.split_once(['.', ',']).unwrap_or((value, ""));
+    if sec.is_empty() || !sec.chars().all(|c| c.is_ascii_digit())
+        || fraction.len() > 9 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", fraction).parse().ok()?
+    };
+    Some((sec.parse().ok()?, nanos))
+}
+
+fn parse_integer<T: std::str::FromStr>(value: &str) -> Option<T> {
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/* YYYY-MM-DD HH:MM:SS, with an optional fraction of second */
+fn parse_fixed_width(time: &str) -> Option<YmdHmsDuration> {
+    if time.len() < 19 || !time.is_ascii() {
         return None;
     }
 
-    let year = time[0 .. 4].parse().ok()?;
+    let year = parse_integer(&time[0 .. 4])?;
     check_char!(time[4], '-');
-    let month = time[5 .. 7].parse().ok()?;
+    let month = parse_integer(&time[5 .. 7])?;
     check_char!(time[7], '-');
-    let day = time[8 .. 10].parse().ok()?;
+    let day = parse_integer(&time[8 .. 10])?;
     check_char!(time[10], ' ');
-    let hour = time[11 .. 13].parse().ok()?;
+    let hour = parse_integer(&time[11 .. 13])?;
     check_char!(time[13], ':');
-    let min = time[14 .. 16].parse().ok()?;
+    let min = parse_integer(&time[14 .. 16])?;
     check_char!(time[16], ':');
-    let sec = time[17 .. 19].parse().ok()?;
+    let (sec, nanos) = match &time[19 ..] {
+    "" => (parse_integer(&time[17 .. 19])?, 0),
+    fraction if fraction.starts_with('.') => parse_seconds(&time[17 ..])?,
+    _ => return None
+    };
 
     Some(YmdHmsDuration {
-        year: year,
-        month: month,
-        day: day,
-        hour: hour,
-        min: min,
-        sec: sec
-    }
-    )
-}
\ No newline at end of file
+        year,
+        month,
+        day,
+        hour,
+        min,
+        sec,
+        nanos,
+        ..YmdHmsDuration::default()
+    })
+}
+
+/* PnYnMnWnDTnHnMnS, only the seconds may have a fraction */
+fn parse_iso8601(time: &str) -> Option<YmdHmsDuration> {
+    let time = time.strip_prefix('P')?;
+    let (date, clock) = match time.split_once('T') {
+    Some((_, "")) => return None,
+    Some((date, clock)) => (date, Some(clock)),
+    None => (time, None)
+    };
+    if date.is_empty() && clock.is_none() {
+        return None;
+    }
+
+    let mut out = YmdHmsDuration::default();
+    let mut number = String::new();
+    /* The designators must come in order, each at most once */
+    let mut order = 0;
+    let mut next = |unit: usize| {
+        let valid = unit >= order;
+        order = unit + 1;
+        valid
+    };
+
+    for c in date.chars() {
+        match c {
+        '0' ..= '9' => number.push(c),
+        'Y' if next(0) => out.year = parse_integer(&number)?,
+        'M' if next(1) => out.month = parse_integer(&number)?,
+        'W' if next(2) => out.week = parse_integer(&number)?,
+        'D' if next(3) => out.day = parse_integer(&number)?,
+        _ => return None
+        }
+        if !c.is_ascii_digit() {
+            number.clear();
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+
+    for c in clock.unwrap_or("").chars() {
+        match c {
+        '0' ..= '9' | '.' | ',' => number.push(c),
+        'H' if next(4) => out.hour = parse_integer(&number)?,
+        'M' if next(5) => out.min = parse_integer(&number)?,
+        'S' if next(6) => (out.sec, out.nanos) = parse_seconds(&number)?,
+        _ => return None
+        }
+        if c.is_ascii_uppercase() {
+            number.clear();
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(out)
+}
+
+/* Amounts followed by their unit, like "1h30m" or "every 2 weeks" */
+fn parse_compact(time: &str) -> Option<YmdHmsDuration> {
+    let (time, every) = match time.strip_prefix("every ") {
+    Some(rest) => (rest.trim(), true),
+    None => (time, false)
+    };
+
+    let mut out = YmdHmsDuration::default();
+    let mut rest = time;
+    let mut first = true;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(digits);
+        let tail = tail.trim_start();
+        let letters = tail.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(letters);
+
+        /* "every week" */
+        let number = match number {
+        "" if every && first && tail.trim().is_empty() => "1",
+        "" => return None,
+        number => number
+        };
+
+        /* Huge amounts are refused rather than wrapped */
+        let add = |total: i64, value: Option<i64>| total.checked_add(value?);
+        match unit.to_ascii_lowercase().as_str() {
+        "ms" | "msec" | "millisecond" | "milliseconds" =>
+            out.nanos = add(out.nanos, parse_integer::<i64>(number)?.checked_mul(1_000_000))?,
+        "s" | "sec" | "secs" | "second" | "seconds" => {
+            let (sec, nanos) = parse_seconds(number)?;
+            out.sec = add(out.sec, Some(sec))?;
+            out.nanos = add(out.nanos, Some(nanos))?;
+        },
+        "m" | "min" | "mins" | "minute" | "minutes" => out.min = add(out.min, parse_integer(number))?,
+        "h" | "hr" | "hrs" | "hour" | "hours" => out.hour = add(out.hour, parse_integer(number))?,
+        "d" | "day" | "days" => out.day = add(out.day, parse_integer(number))?,
+        "w" | "week" | "weeks" => out.week = add(out.week, parse_integer(number))?,
+        "mo" | "month" | "months" => out.month = out.month.checked_add(parse_integer(number)?)?,
+        "y" | "year" | "years" => out.year = out.year.checked_add(parse_integer(number)?)?,
+        _ => return None
+        }
+
+        rest = tail.trim_start();
+        first = false;
+    }
+
+    out.sec = out.sec.checked_add(out.nanos / 1_000_000_000)?;
+    out.nanos %= 1_000_000_000;
+    (!first).then_some(out)
+}
+
+/* Accepts the fixed-width form (0000-00-00 00:01:00), ISO 8601 durations
+ * (PT1M) and compact forms (1m, every minute). Empty periods are refused.
+ */
+pub fn get_period_from_string(time: &str) -> Option<YmdHmsDuration> {
+    let time = time.trim();
+    let out = parse_fixed_width(time)
+        .or_else(|| parse_iso8601(time))
+        .or_else(|| parse_compact(time))?;
+
+    (!out.is_zero()).then_some(out)
+}
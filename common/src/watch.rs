@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap, ffi::{CString, OsStr}, fs::File, io::{self, Read}, os::{fd::{AsRawFd, FromRawFd}, unix::ffi::OsStrExt}, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}
+};
+
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEvent {
+    Create,
+    /* A file opened for writing was closed */
+    CloseWrite,
+    /* A file was moved into the directory */
+    Move,
+}
+
+impl FileEvent {
+    fn mask(self) -> u32 {
+        match self {
+        FileEvent::Create => libc::IN_CREATE,
+        FileEvent::CloseWrite => libc::IN_CLOSE_WRITE,
+        FileEvent::Move => libc::IN_MOVED_TO,
+        }
+    }
+}
+
+fn default_events() -> Vec<FileEvent> {
+    vec![FileEvent::CloseWrite, FileEvent::Move]
+}
+
+/* Runs the group for the files of a directory whose names match a
+ * pattern, like the CSV files of /srv/incoming, or for every file of a
+ * directory. Only the file name may have wildcards (star, question mark
+ * and brackets). Hidden files are only matched by patterns starting with
+ * a dot.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FileTrigger {
+    pub path: PathBuf,
+    #[serde(default = "default_events")]
+    pub events: Vec<FileEvent>,
+    /* Seconds without events on a file before it triggers a run */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce: Option<u64>,
+    /* Seconds the file must not have been modified for */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable_for: Option<u64>,
+}
+
+fn has_wildcards(x: &str) -> bool {
+    x.contains(['*', '?', '['])
+}
+
+/* Matches whole names */
+pub fn glob_to_regex(pattern: &str) -> Result<Regex, String> {
+    let mut out = String::from("^");
+
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+        '*' => out.push_str("[^/]*"),
+        '?' => out.push_str("[^/]"),
+        '[' => {
+            out.push('[');
+            let mut first = true;
+            loop {
+                match chars.next() {
+                Some(']') if !first => break,
+                Some('!') if first => out.push('^'),
+                Some(c) if c == '\\' || c == '[' || c == ']' => {
+                    out.push('\\');
+                    out.push(c);
+                },
+                Some(c) => out.push(c),
+                None => return Err(format!("Unclosed bracket in {}", pattern))
+                }
+                first = false;
+            }
+            out.push(']');
+        },
+        c => out.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+    out.push('$');
+
+    Regex::new(&out).map_err(|e| e.to_string())
+}
+
+impl FileTrigger {
+    /* Directory to watch, and the pattern of the file names */
+    fn split(&self) -> Result<(PathBuf, String), String> {
+        let path = self.path.to_string_lossy();
+        if self.path.is_dir() && !has_wildcards(&path) {
+            return Ok((self.path.clone(), String::from("*")));
+        }
+
+        let dir = self.path.parent()
+            .filter(|x| !x.as_os_str().is_empty())
+            .ok_or_else(|| format!("No directory in {}", path))?;
+        if has_wildcards(&dir.to_string_lossy()) {
+            return Err(format!("Only file names may have wildcards: {}", path));
+        }
+        let name = self.path.file_name()
+            .ok_or_else(|| format!("No file name in {}", path))?;
+
+        let name = name.to_string_lossy().into_owned();
+        glob_to_regex(&name)?;
+        Ok((dir.to_path_buf(), name))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.path.is_absolute() {
+            return Err(format!("The watched path must be absolute: {}", self.path.display()));
+        }
+        if self.events.is_empty() {
+            return Err(String::from("No file event to watch"));
+        }
+        self.split().map(|_| ())
+    }
+}
+
+/* Files that had events, with the last one */
+#[derive(Debug)]
+pub struct FileWatcher {
+    trigger: FileTrigger,
+    inotify: File,
+    dir: PathBuf,
+    pattern: Regex,
+    hidden: bool,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+/* Size of the fixed part of struct inotify_event */
+const EVENT_SIZE: usize = 16;
+
+impl FileWatcher {
+    pub fn new(trigger: FileTrigger) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let (dir, pattern) = trigger.split().map_err(invalid)?;
+        let hidden = pattern.starts_with('.');
+        let pattern = glob_to_regex(&pattern).map_err(invalid)?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = unsafe { File::from_raw_fd(fd) };
+
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| invalid(e.to_string()))?;
+        let mask = trigger.events.iter()
+            .fold(libc::IN_ONLYDIR, |acc, x| acc | x.mask());
+        if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            trigger,
+            inotify,
+            dir,
+            pattern,
+            hidden,
+            pending: HashMap::new(),
+        })
+    }
+
+    fn read_events(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = match self.inotify.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e)
+            };
+
+            let mut offset = 0;
+            while offset + EVENT_SIZE <= len {
+                let field = |at: usize| u32::from_ne_bytes(buf[offset + at .. offset + at + 4].try_into().unwrap());
+                let mask = field(4);
+                let name_len = field(12) as usize;
+                let name = &buf[offset + EVENT_SIZE .. offset + EVENT_SIZE + name_len];
+                let name = OsStr::from_bytes(name.split(|x| *x == 0).next().unwrap_or_default());
+                offset += EVENT_SIZE + name_len;
+
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    warn!("Too many events on {:?}, some were lost", self.dir);
+                }
+                /* The names don't have to be UTF-8, only the pattern does */
+                let lossy = name.to_string_lossy();
+                if !name.is_empty()
+                    && (self.hidden || !lossy.starts_with('.'))
+                    && self.pattern.is_match(&lossy) {
+                    self.pending.insert(self.dir.join(name), Instant::now());
+                }
+            }
+        }
+    }
+
+    fn is_stable(&self, path: &Path) -> bool {
+        let Some(stable_for) = self.trigger.stable_for else {
+            return true;
+        };
+        path.metadata()
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| SystemTime::now().duration_since(x).ok())
+            .is_some_and(|x| x >= Duration::from_secs(stable_for))
+    }
+
+    /* Files that are ready, once quiet for the debounce interval and stable.
+     * The ones that went away in the meantime are forgotten. */
+    pub fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+        self.read_events()?;
+
+        let debounce = Duration::from_secs(self.trigger.debounce.unwrap_or(0));
+        let now = Instant::now();
+        let mut out = Vec::new();
+        for (path, last) in std::mem::take(&mut self.pending) {
+            if now.duration_since(last) < debounce {
+                self.pending.insert(path, last);
+            } else if !path.exists() {
+                debug!("{:?} is gone", path);
+            } else if !self.is_stable(&path) {
+                self.pending.insert(path, last);
+            } else {
+                out.push(path);
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+}
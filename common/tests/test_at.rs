@@ -0,0 +1,51 @@
+use chrono::{TimeZone, Utc};
+use common::at::{parse_time, AtRequest};
+use serde_json::json;
+
+#[test]
+fn test_parse_time() {
+    let now = Utc.with_ymd_and_hms(2026, 10, 19, 12, 0, 0).unwrap();
+    let at = |h: u32, m: u32| Some(Utc.with_ymd_and_hms(2026, 11, 1, h, m, 0).unwrap());
+
+    assert_eq!(parse_time("now", now), Some(now));
+    assert_eq!(parse_time("now + 1h", now), Some(now + chrono::Duration::hours(1)));
+    assert_eq!(parse_time("+PT30M", now), Some(now + chrono::Duration::minutes(30)));
+    assert_eq!(parse_time("2026-11-01T03:00Z", now), at(3, 0));
+    assert_eq!(parse_time("2026-11-01 03:00", now), at(3, 0));
+    assert_eq!(parse_time("2026-11-01T03:00:00Z", now), at(3, 0));
+    assert_eq!(parse_time("2026-11-01T04:30:00+01:00", now), at(3, 30));
+    assert_eq!(parse_time("tomorrow", now), None);
+    assert_eq!(parse_time("now + soon", now), None);
+
+    /* Refused, without panicking */
+    for time in [
+        "2026-11-01T03:", "2026-11-01T03", "2026", "2026-11-01T03:00:00é",
+        "é2026-11-01T03:00:00Z", "2026-11-0é 03:00", "2026-13-01T00:00:00Z",
+        "2026-02-30T00:00:00Z", "2026-11-01T25:00", "now + 9000y", "+P99999999Y"
+    ] {
+        assert_eq!(parse_time(time, now), None, "{}", time);
+    }
+}
+
+#[test]
+fn test_request() {
+    let request: AtRequest = serde_json::from_value(json!({
+        "time": "now + 5m",
+        "cmd": {"program": "echo", "args": ["a", "b"]}
+    })).unwrap();
+    assert!(request.validate().is_ok());
+    assert_eq!(request.describe(), "echo a b");
+    assert_eq!(request.task().cmd.arguments, vec!["a", "b"]);
+
+    let request: AtRequest = serde_json::from_value(json!({
+        "time": "yesterday",
+        "cmd": {"program": "true"}
+    })).unwrap();
+    assert!(request.validate().is_err());
+
+    let request: AtRequest = serde_json::from_value(json!({
+        "time": "2026-11-01T0é",
+        "cmd": {"program": "true"}
+    })).unwrap();
+    assert!(request.validate().is_err());
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{calendar::{next_allowed, parse_ics, Calendar, CalendarConstraints}, group::{SerializedTaskGroup, TaskGroup}};
+use serde_json::json;
+
+fn time(d: u32, h: u32, m: u32) -> DateTime<Utc> {
+    /* 2025-06-02 is a Monday */
+    Utc.with_ymd_and_hms(2025, 6, d, h, m, 0).unwrap()
+}
+
+fn calendar(json: &str) -> Calendar {
+    let mut out: Calendar = serde_json::from_str(json).unwrap();
+    out.load().unwrap();
+    out
+}
+
+fn business_hours() -> Calendar {
+    calendar(r#"{"weekly": [{"days": ["mon", "tue", "wed", "thu", "fri"], "from": "09:00", "to": "18:00"}]}"#)
+}
+
+#[test]
+fn test_weekly() {
+    let calendar = business_hours();
+    assert!(calendar.contains(time(2, 9, 0)));
+    assert!(calendar.contains(time(6, 17, 59)));
+    assert!(!calendar.contains(time(2, 18, 0)));
+    assert!(!calendar.contains(time(2, 8, 59)));
+    assert!(!calendar.contains(time(7, 12, 0)));
+}
+
+#[test]
+fn test_weekly_overnight() {
+    let calendar = calendar(r#"{"weekly": [{"days": ["Sun"], "from": "22:00", "to": "02:00"}]}"#);
+    assert!(calendar.contains(time(1, 23, 0)));
+    assert!(calendar.contains(time(2, 1, 59)));
+    assert!(!calendar.contains(time(2, 2, 0)));
+    assert!(!calendar.contains(time(2, 23, 0)));
+}
+
+#[test]
+fn test_dates_and_ranges() {
+    let calendar = calendar(r#"{
+        "dates": ["2025-06-04"],
+        "ranges": [
+            {"from": "2025-06-10", "to": "2025-06-11"},
+            {"from": "2025-06-20T10:00:00Z", "to": "2025-06-20T12:00:00+01:00"}
+        ]
+    }"#);
+    assert!(calendar.contains(time(4, 0, 0)));
+    assert!(calendar.contains(time(4, 23, 59)));
+    assert!(!calendar.contains(time(5, 0, 0)));
+    assert!(calendar.contains(time(11, 23, 0)));
+    assert!(!calendar.contains(time(12, 0, 0)));
+    assert!(calendar.contains(time(20, 10, 30)));
+    assert!(!calendar.contains(time(20, 11, 0)));
+}
+
+#[test]
+fn test_invalid() {
+    let mut calendar: Calendar = serde_json::from_str(
+        r#"{"weekly": [{"days": ["mon"], "from": "9h", "to": "18:00"}]}"#
+    ).unwrap();
+    assert!(calendar.load().is_err());
+
+    let mut calendar: Calendar = serde_json::from_str(r#"{"dates": ["04/06/2025"]}"#).unwrap();
+    assert!(calendar.load().is_err());
+}
+
+#[test]
+fn test_ics() {
+    let content = "BEGIN:VCALENDAR\r\n\
+        BEGIN:VEVENT\r\n\
+        SUMMARY:Freeze\r\n\
+        DTSTART;VALUE=DATE:20250609\r\n\
+        DTEND;VALUE=DATE:20250611\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VEVENT\r\n\
+        DTSTART:20250620T100000Z\r\n\
+        DTEND:20250620T1\r\n \
+        10000Z\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VEVENT\r\n\
+        DTSTART;VALUE=DATE:20250625\r\n\
+        END:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    assert_eq!(parse_ics(content).unwrap(), vec![
+        (time(9, 0, 0), time(11, 0, 0)),
+        (time(20, 10, 0), time(20, 11, 0)),
+        (time(25, 0, 0), time(26, 0, 0)),
+    ]);
+
+    assert!(parse_ics("BEGIN:VEVENT\nDTSTART:tomorrow\nEND:VEVENT\n").is_err());
+}
+
+#[test]
+fn test_windows() {
+    let business = business_hours();
+    assert_eq!(business.end_of_window(time(2, 10, 0)), Some(time(2, 18, 0)));
+    assert_eq!(business.end_of_window(time(2, 20, 0)), Some(time(2, 20, 0)));
+    assert_eq!(business.start_of_window(time(2, 20, 0)), Some(time(3, 9, 0)));
+    assert_eq!(business.start_of_window(time(6, 20, 0)), Some(time(9, 9, 0)));
+    assert_eq!(business.start_of_window(time(3, 12, 0)), Some(time(3, 12, 0)));
+
+    /* Adjacent intervals make a single window */
+    let days = calendar(r#"{"dates": ["2025-06-04", "2025-06-05"]}"#);
+    assert_eq!(days.end_of_window(time(4, 12, 0)), Some(time(6, 0, 0)));
+    assert_eq!(days.start_of_window(time(6, 0, 0)), None);
+}
+
+#[test]
+fn test_next_allowed() {
+    let business = business_hours();
+    let freeze = calendar(r#"{"ranges": [{"from": "2025-06-03", "to": "2025-06-04"}]}"#);
+
+    assert_eq!(next_allowed(time(2, 12, 0), None, None), Some(time(2, 12, 0)));
+    assert_eq!(next_allowed(time(2, 12, 0), None, Some(&business)), Some(time(2, 18, 0)));
+    assert_eq!(next_allowed(time(2, 20, 0), Some(&business), None), Some(time(3, 9, 0)));
+    assert_eq!(next_allowed(time(2, 20, 0), Some(&business), Some(&freeze)), Some(time(5, 9, 0)));
+    assert_eq!(next_allowed(time(2, 20, 0), Some(&freeze), Some(&freeze)), None);
+}
+
+#[test]
+fn test_constraints() {
+    let constraints: CalendarConstraints = serde_json::from_str(
+        r#"{"never_during": "business", "blackout_policy": "defer"}"#
+    ).unwrap();
+    assert_eq!(constraints.names().collect::<Vec<_>>(), vec!["business"]);
+
+    let mut calendars = HashMap::new();
+    assert!(constraints.next_allowed(time(2, 12, 0), &calendars).is_err());
+
+    calendars.insert(String::from("business"), business_hours());
+    assert_eq!(constraints.next_allowed(time(2, 12, 0), &calendars), Ok(Some(time(2, 18, 0))));
+}
+
+#[test]
+fn test_deferral_persisted() {
+    let today = Utc::now().date_naive();
+    let freeze = calendar(&json!({"ranges": [{
+        "from": (today - chrono::Duration::days(1)).to_string(),
+        "to": (today + chrono::Duration::days(2)).to_string()
+    }]}).to_string());
+    let calendars = Arc::new(HashMap::from([(String::from("freeze"), freeze)]));
+
+    let conf: SerializedTaskGroup = serde_json::from_value(json!({
+        "name": "deferred",
+        "starts_at": "****-**-**T**:**:**Z",
+        "period": "PT1S",
+        "never_during": "freeze",
+        "blackout_policy": "defer",
+        "processes": [{"name": "t", "cmd": {"program": "/bin/true", "chdir": "/"}}]
+    })).unwrap();
+    let mut group = TaskGroup::from(conf);
+    group.set_calendars(calendars.clone());
+
+    for _ in 0 .. 30 {
+        group.update();
+        if group.take_dirty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let deferred_to = group.next_execution().unwrap();
+    assert!(deferred_to > Utc::now() + chrono::Duration::days(1));
+
+    let saved = serde_json::to_value(&group).unwrap();
+    assert!(saved["deferred_from"].is_string());
+    let mut group: TaskGroup = serde_json::from_value(saved).unwrap();
+    group.set_calendars(calendars);
+    assert_eq!(group.next_execution(), Some(deferred_to));
+}
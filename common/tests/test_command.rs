@@ -0,0 +1,467 @@
+use std::os::unix::{fs::PermissionsExt, process::ExitStatusExt};
+
+use common::{command::{Command, EnvValue, Log, TaskOutput}, env::RunContext, secrets::{self, SecretStore}};
+use serde_json::json;
+
+fn shell(script: &str) -> Command {
+    serde_json::from_value(json!({
+        "program": "/bin/sh",
+        "args": ["-c", script],
+        "chdir": "/"
+    })).unwrap()
+}
+
+#[test]
+fn test_command_captures_output() {
+    match shell("echo out; echo err >&2").run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            assert!(outcome.is_success());
+            assert!(matches!(outcome.stdout, Log::Buffer(ref x) if x == b"out\n"));
+            assert!(matches!(outcome.stderr, Log::Buffer(ref x) if x == b"err\n"));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_reports_exit_code() {
+    match shell("exit 3").run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            assert!(!outcome.is_success());
+            assert_eq!(outcome.exit_status.code(), Some(3));
+            assert!(matches!(outcome.stdout, Log::Nothing));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_reports_resource_usage() {
+    match shell("i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done").run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            let usage = outcome.usage;
+            assert!(usage.max_rss > 0);
+            assert!(usage.user_time + usage.system_time > std::time::Duration::ZERO);
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_applies_limits() {
+    let mut cmd = shell("ulimit -n; ulimit -c; umask; nice");
+    cmd.limits = Some(serde_json::from_value(json!({
+        "nofile": 64,
+        "core": 0,
+        "umask": "0027",
+        "nice": 5
+    })).unwrap());
+
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            assert!(outcome.is_success());
+            assert!(matches!(outcome.stdout, Log::Buffer(ref x) if x == b"64\n0\n0027\n5\n"));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_limits_validation() {
+    let with_limits = |limits: serde_json::Value| {
+        let mut cmd = shell("true");
+        cmd.limits = Some(serde_json::from_value(limits).unwrap());
+        cmd.validate()
+    };
+    assert!(with_limits(json!({"ioprio": {"class": "best-effort", "level": 7}})).is_ok());
+    assert!(with_limits(json!({"ioprio": {"class": "best-effort", "level": 8}})).is_err());
+    assert!(with_limits(json!({"cpu_affinity": [0]})).is_ok());
+    assert!(with_limits(json!({"cpu_affinity": [0, 1024]})).is_err());
+    assert!(with_limits(json!({"cpu_affinity": []})).is_err());
+}
+
+#[test]
+fn test_command_times_out() {
+    let mut cmd = shell("sleep 10");
+    cmd.timeout = Some(1);
+
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::TimedOut(outcome) => {
+            assert!(outcome.exit_status.signal().is_some());
+            assert!(outcome.duration < std::time::Duration::from_secs(5));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_sandbox() {
+    let mut cmd = shell("echo $$; hostname; touch /etc/sandbox-test; ls /tmp | wc -l; unshare -n true 2>/dev/null || echo denied");
+    cmd.sandbox = Some(serde_json::from_value(json!({
+        "pid": true,
+        "network": true,
+        "hostname": "sandbox",
+        "read_only": ["/etc"],
+        "private_tmp": true,
+        "seccomp_deny": ["unshare", "mount"]
+    })).unwrap());
+
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            assert!(matches!(outcome.stdout, Log::Buffer(ref x) if x == b"1\nsandbox\n0\ndenied\n"));
+            assert!(!std::path::Path::new("/etc/sandbox-test").exists());
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_sandbox_unknown_syscall() {
+    let mut cmd = shell("true");
+    cmd.sandbox = Some(serde_json::from_value(json!({
+        "seccomp_deny": ["not_a_syscall"]
+    })).unwrap());
+
+    assert!(matches!(cmd.run(&RunContext::default()), TaskOutput::IOError(_)));
+}
+
+#[test]
+fn test_command_sandbox_no_new_privs() {
+    let mut cmd = shell("true");
+    cmd.sandbox = Some(serde_json::from_value(json!({
+        "seccomp_deny": ["ptrace"],
+        "no_new_privs": false
+    })).unwrap());
+    cmd.uid = Some(0);
+    assert!(cmd.validate().is_ok());
+
+    /* Only root may install the filter without it */
+    cmd.uid = Some(65534);
+    assert!(cmd.validate().unwrap_err().contains("no_new_privs"));
+
+    cmd.sandbox.as_mut().unwrap().no_new_privs = true;
+    assert!(cmd.validate().is_ok());
+    assert!(matches!(cmd.run(&RunContext::default()), TaskOutput::NoError(x) if x.is_success()));
+}
+
+#[test]
+fn test_command_user_name() {
+    let mut cmd = shell("id -u; id -G; echo $HOME $USER $LOGNAME");
+    cmd.user = Some(String::from("nobody"));
+
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            let stdout = match outcome.stdout {
+                Log::Buffer(x) => String::from_utf8(x).unwrap(),
+                _ => panic!("Missing stdout")
+            };
+            let lines: Vec<&str> = stdout.lines().collect();
+            assert_ne!(lines[0], "0");
+            assert!(!lines[1].split(' ').any(|x| x == "0"));
+            assert_eq!(lines[2], "/nonexistent nobody nobody");
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_validate_unknown_user() {
+    let mut cmd = shell("true");
+    cmd.user = Some(String::from("no-such-user-hopefully"));
+    assert!(cmd.validate().is_err());
+
+    cmd.user = Some(String::from("nobody"));
+    assert!(cmd.validate().is_ok());
+
+    cmd.group = Some(String::from("no-such-group-hopefully"));
+    assert!(cmd.validate().is_err());
+}
+
+fn stdout_of(cmd: &Command, ctx: &RunContext) -> String {
+    match cmd.run(ctx) {
+        TaskOutput::NoError(outcome) => match outcome.stdout {
+            Log::Buffer(x) => String::from_utf8(x).unwrap(),
+            _ => String::new()
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_environment() {
+    let dir = std::env::temp_dir().join("scheduler-test-env");
+    std::fs::create_dir_all(&dir).unwrap();
+    let env_file = dir.join("env");
+    std::fs::write(&env_file, "# Comment\nexport A=\"from file\"\nB=${A}!\n").unwrap();
+
+    let mut cmd = shell("env | sort");
+    cmd.env_clear = true;
+    cmd.env_files = vec![env_file];
+    cmd.envs = Some([
+        (String::from("C"), EnvValue::Plain(String::from("${B} ${SCHEDULER_TASK} $${A}"))),
+    ].into_iter().collect());
+
+    let ctx = RunContext {
+        group: String::from("group"),
+        task: String::from("task"),
+        run_id: 3,
+        attempt: 1,
+        ..RunContext::default()
+    };
+
+    let stdout = stdout_of(&cmd, &ctx);
+    let vars: Vec<&str> = stdout.lines()
+        .filter(|x| !x.starts_with("PWD=") && !x.starts_with("SHLVL=") && !x.starts_with("_="))
+        .collect();
+    assert_eq!(vars, vec![
+        "A=from file",
+        "B=from file!",
+        "C=from file! task ${A}",
+        "SCHEDULER_ATTEMPT=1",
+        "SCHEDULER_FIRE_TIME=1970-01-01T00:00:00+00:00",
+        "SCHEDULER_GROUP=group",
+        "SCHEDULER_RUN_ID=3",
+        "SCHEDULER_SCHEDULED_TIME=1970-01-01T00:00:00+00:00",
+        "SCHEDULER_TASK=task",
+    ]);
+}
+
+#[test]
+fn test_command_environment_inherit() {
+    let mut cmd = shell("echo ${PATH:-none} ${CARGO:-none}");
+    cmd.env_inherit = Some(vec![String::from("PATH")]);
+
+    let stdout = stdout_of(&cmd, &RunContext::default());
+    assert!(!stdout.starts_with("none"));
+    assert!(stdout.ends_with(" none\n"));
+}
+
+fn secret_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+    dir
+}
+
+fn private_file(path: &std::path::Path, content: &[u8]) {
+    std::fs::write(path, content).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).unwrap();
+}
+
+#[test]
+fn test_command_secret_directory() {
+    let dir = secret_dir("scheduler-test-secrets");
+    private_file(&dir.join("password"), b"hunter22\n");
+
+    let mut cmd = shell("echo \"pass=$PASSWORD\"; echo $PASSWORD >&2");
+    cmd.envs = Some(serde_json::from_value(json!({
+        "PASSWORD": {"secret": "password"}
+    })).unwrap());
+
+    let ctx = RunContext {
+        secrets: Some(SecretStore::Directory(dir.clone())),
+        ..RunContext::default()
+    };
+    match cmd.run(&ctx) {
+        TaskOutput::NoError(outcome) => {
+            assert!(matches!(outcome.stdout, Log::Buffer(ref x) if x == b"pass=********\n"));
+            assert!(matches!(outcome.stderr, Log::Buffer(ref x) if x == b"********\n"));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+
+    let conf = serde_json::to_string(&cmd).unwrap();
+    assert!(!conf.contains("hunter22"));
+
+    std::fs::set_permissions(dir.join("password"), std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(matches!(cmd.run(&ctx), TaskOutput::IOError(_)));
+}
+
+#[test]
+fn test_command_secret_encrypted_file() {
+    let dir = secret_dir("scheduler-test-sealed");
+    let key = dir.join("key");
+    private_file(&key, &[7; 32]);
+    let sealed = secrets::seal(&key, br#"{"token": "s3cr3t-t0ken"}"#).unwrap();
+    private_file(&dir.join("secrets"), &sealed);
+
+    let store = SecretStore::EncryptedFile { path: dir.join("secrets"), key };
+    assert_eq!(store.resolve("token").unwrap(), "s3cr3t-t0ken");
+    assert!(store.resolve("missing").is_err());
+}
+
+fn succeeds(cmd: &Command) -> bool {
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => outcome.is_success(),
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+fn with_criteria(script: &str, criteria: serde_json::Value) -> Command {
+    let mut conf = json!({
+        "program": "/bin/sh",
+        "args": ["-c", script],
+        "chdir": "/"
+    });
+    conf.as_object_mut().unwrap().extend(criteria.as_object().unwrap().clone());
+    serde_json::from_value(conf).unwrap()
+}
+
+#[test]
+fn test_command_success_exit_codes() {
+    let criteria = json!({"success_exit_codes": [0, 1]});
+    assert!(succeeds(&with_criteria("exit 1", criteria.clone())));
+    assert!(!succeeds(&with_criteria("exit 2", criteria)));
+
+    assert!(!succeeds(&with_criteria("exit 0", json!({"failure_exit_codes": [0]}))));
+    assert!(with_criteria("exit 0", json!({
+        "success_exit_codes": [0, 3],
+        "failure_exit_codes": [3]
+    })).validate().is_err());
+}
+
+#[test]
+fn test_command_fail_on_signal() {
+    assert!(!succeeds(&shell("kill -TERM $$")));
+    assert!(succeeds(&with_criteria("kill -TERM $$", json!({"fail_on_signal": false}))));
+}
+
+#[test]
+fn test_command_output_matchers() {
+    let cmd = with_criteria("echo 'ERROR: disk full' >&2", json!({
+        "fail_if_output_matches": "^ERROR"
+    }));
+    assert!(!succeeds(&cmd));
+
+    let cmd = with_criteria("echo 'nothing to do'; exit 1", json!({
+        "succeed_if_output_matches": "nothing to do"
+    }));
+    assert!(succeeds(&cmd));
+
+    let cmd = with_criteria("echo 'nothing to do'; echo ERROR", json!({
+        "fail_if_output_matches": "(?m)^ERROR",
+        "succeed_if_output_matches": "nothing to do"
+    }));
+    assert!(!succeeds(&cmd));
+
+    assert!(with_criteria("true", json!({"fail_if_output_matches": "("})).validate().is_err());
+}
+
+fn script(conf: serde_json::Value) -> Command {
+    let mut base = json!({"chdir": "/"});
+    base.as_object_mut().unwrap().extend(conf.as_object().unwrap().clone());
+    serde_json::from_value(base).unwrap()
+}
+
+#[test]
+fn test_command_script_file() {
+    let cmd = script(json!({
+        "script": "echo \"$1-$2\"\nfalse\necho unreachable",
+        "args": ["a", "b"]
+    }));
+    cmd.validate().unwrap();
+
+    match cmd.run(&RunContext::default()) {
+        TaskOutput::NoError(outcome) => {
+            assert!(!outcome.is_success());
+            assert!(matches!(outcome.stdout, Log::Buffer(ref x) if x == b"a-b\n"));
+            /* sha256sum of the script */
+            assert_eq!(outcome.script_hash.as_deref(),
+                Some("8a59fc930571632a69860bf1d7feef187205769b1048a90b2e46b72e556903f7"));
+        },
+        output => panic!("Unexpected output: {}", output.summary())
+    }
+}
+
+#[test]
+fn test_command_script_stdin() {
+    let cmd = script(json!({
+        "script": "echo \"$1\" {{task}}",
+        "script_input": "stdin",
+        "args": ["x"]
+    }));
+    let ctx = RunContext { task: String::from("t"), ..RunContext::default() };
+    assert_eq!(stdout_of(&cmd, &ctx), "x t\n");
+
+    let cmd = script(json!({
+        "script": "import sys; print(sys.argv[1])",
+        "interpreter": ["/usr/bin/env", "python3", "-"],
+        "script_input": "stdin",
+        "args": ["py"]
+    }));
+    if std::path::Path::new("/usr/bin/python3").exists() {
+        assert_eq!(stdout_of(&cmd, &RunContext::default()), "py\n");
+    }
+}
+
+/* Like Go templates, in `docker ps --format '{{.Names}}'` */
+#[test]
+fn test_command_literal_braces() {
+    let cmd = shell("printf '%s\\n' '{{{{.Names}}'");
+    cmd.validate().unwrap();
+    assert_eq!(stdout_of(&cmd, &RunContext::default()), "{{.Names}}\n");
+
+    let cmd = script(json!({"script": "printf '%s\\n' '{{{{.Names}}' {{task}}"}));
+    cmd.validate().unwrap();
+    let ctx = RunContext { task: String::from("t"), ..RunContext::default() };
+    assert_eq!(stdout_of(&cmd, &ctx), "{{.Names}}\nt\n");
+
+    assert!(shell("printf '%s\\n' '{{.Names}}'").validate().is_err());
+}
+
+#[test]
+fn test_command_script_pipefail() {
+    if !std::path::Path::new("/bin/bash").exists() {
+        return;
+    }
+
+    let conf = json!({
+        "script": "false | true\necho after",
+        "interpreter": ["/bin/bash", "-e"]
+    });
+    assert!(succeeds(&script(conf.clone())));
+
+    let mut conf = conf;
+    conf["pipefail"] = json!(true);
+    assert!(!succeeds(&script(conf)));
+
+    /* Bash is the default interpreter with pipefail */
+    let conf = json!({"script": "false | true\necho after", "pipefail": true});
+    script(conf.clone()).validate().unwrap();
+    assert!(!succeeds(&script(conf)));
+}
+
+#[test]
+fn test_command_script_pipefail_interpreter() {
+    let with = |interpreter: serde_json::Value| script(json!({
+        "script": "true",
+        "pipefail": true,
+        "interpreter": interpreter
+    }));
+    assert!(with(json!(["/bin/sh", "-e"])).validate().is_err());
+    assert!(with(json!(["/usr/bin/env", "dash"])).validate().is_err());
+    with(json!(["/usr/bin/env", "-S", "bash", "-e"])).validate().unwrap();
+    with(json!(["/bin/zsh"])).validate().unwrap();
+}
+
+#[test]
+fn test_command_script_validate() {
+    assert!(script(json!({"program": "/bin/true", "script": "true"})).validate().is_err());
+    assert!(script(json!({})).validate().is_err());
+    assert!(script(json!({"program": "/bin/true", "interpreter": ["/bin/sh"]})).validate().is_err());
+    assert!(script(json!({"script": "true", "interpreter": []})).validate().is_err());
+    assert!(script(json!({"script": "true", "sandbox": {"private_tmp": true}})).validate().is_err());
+    script(json!({"script": "true", "script_input": "stdin", "sandbox": {"private_tmp": true}}))
+        .validate().unwrap();
+}
+
+#[test]
+fn test_command_stdin_file() {
+    let path = std::env::temp_dir().join("scheduler-test-stdin");
+    std::fs::write(&path, "from a file\n").unwrap();
+
+    let mut cmd = shell("cat");
+    cmd.stdin = Some(serde_json::from_value(json!({"file": path})).unwrap());
+    assert_eq!(stdout_of(&cmd, &RunContext::default()), "from a file\n");
+}
@@ -0,0 +1,377 @@
+use std::{os::unix::fs::PermissionsExt, thread, time::{Duration, Instant}};
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{command::TaskOutput, group::{SerializedTaskGroup, TaskGroup}};
+use serde_json::json;
+
+fn group(name: &str, processes: serde_json::Value) -> SerializedTaskGroup {
+    serde_json::from_value(json!({
+        "name": name,
+        "starts_at": "****-**-**T**:**:**Z",
+        "period": "0000-00-00 00:00:01",
+        "processes": processes
+    })).unwrap()
+}
+
+fn task(name: &str, script: &str, stdin: Option<serde_json::Value>) -> serde_json::Value {
+    json!({
+        "name": name,
+        "cmd": {"script": script, "chdir": "/", "stdin": stdin}
+    })
+}
+
+/* Updates the group until the given task ran once */
+fn wait_for(group: &mut TaskGroup, id: usize) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        group.update();
+        let done = group.tasks()[id].iter()
+            .next()
+            .is_some_and(|x| !matches!(x, TaskOutput::Waiting));
+        if done {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_stdin_from_task() {
+    let conf = group("pipeline", json!([
+        task("mail", "tr a-z A-Z", Some(json!({"task": "report"}))),
+        task("report", "sleep 0.2; echo report of {{run_id}}", None),
+        task("header", "cat", Some(json!({"text": "for {{group}}"}))),
+    ]));
+    conf.validate().unwrap();
+    let mut group = TaskGroup::from(conf);
+
+    assert!(wait_for(&mut group, 0));
+    assert_eq!(group.tasks()[0].stdout(0).unwrap(), b"REPORT OF 1\n");
+
+    assert!(wait_for(&mut group, 2));
+    assert_eq!(group.tasks()[2].stdout(0).unwrap(), b"for pipeline");
+}
+
+#[test]
+fn test_stdin_from_failed_task() {
+    let mut group = TaskGroup::from(group("failing", json!([
+        task("report", "exit 1", None),
+        task("mail", "cat", Some(json!({"task": "report"}))),
+    ])));
+
+    assert!(wait_for(&mut group, 0));
+    for _ in 0 .. 10 {
+        group.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(group.tasks()[1].iter().count(), 0);
+}
+
+#[test]
+fn test_label_validation() {
+    let conf = group("invalid", json!([
+        task("a", "true", None),
+        task("a", "true", None),
+    ]));
+    assert!(conf.validate().unwrap_err().contains("labelled \"a\""));
+
+    /* The label of an unnamed task is its index */
+    let mut conf = serde_json::to_value(group("invalid", json!([
+        task("1", "true", None),
+        task("b", "true", None),
+    ]))).unwrap();
+    conf["processes"][1]["name"] = serde_json::Value::Null;
+    let conf: SerializedTaskGroup = serde_json::from_value(conf).unwrap();
+    assert!(conf.validate().unwrap_err().contains("labelled \"1\""));
+
+    let conf = group("valid", json!([
+        task("1", "true", None),
+        task("0", "true", None),
+    ]));
+    assert!(conf.validate().is_ok());
+}
+
+#[test]
+fn test_stdin_validation() {
+    let conf = group("invalid", json!([
+        task("mail", "cat", Some(json!({"task": "nothing"}))),
+    ]));
+    assert!(conf.validate().is_err());
+
+    let conf = group("invalid", json!([
+        task("a", "cat", Some(json!({"task": "b"}))),
+        task("b", "cat", Some(json!({"task": "c"}))),
+        task("c", "cat", Some(json!({"task": "a"}))),
+    ]));
+    assert!(conf.validate().unwrap_err().contains("Cycle"));
+
+    let conf = group("invalid", json!([
+        task("a", "cat", Some(json!({"task": "a"}))),
+    ]));
+    assert!(conf.validate().is_err());
+
+    let conf = group("invalid", json!([
+        {"cmd": {"script": "cat", "script_input": "stdin", "stdin": {"text": "x"}, "chdir": "/"}},
+    ]));
+    assert!(conf.validate().is_err());
+}
+
+#[test]
+fn test_outputs() {
+    let conf = group("outputs", json!([
+        {
+            "name": "backup",
+            "cmd": {"script": "touch \"$SCHEDULER_RUN_DIR/dump\"\necho file=dump.tar >> \"$SCHEDULER_OUTPUTS\"\necho ROWS=42 >> \"$SCHEDULER_OUTPUTS\"", "chdir": "/"}
+        },
+        {
+            "name": "check",
+            "depends_on": ["backup"],
+            "cmd": {"script": "echo \"$SCHEDULER_RUN_DIR\"; ls \"$SCHEDULER_RUN_DIR\"; echo {{output.file}} $SCHEDULER_OUTPUT_ROWS", "chdir": "/"}
+        },
+    ]));
+    conf.validate().unwrap();
+    let mut group = TaskGroup::from(conf);
+
+    assert!(wait_for(&mut group, 1));
+    let stdout = String::from_utf8(group.tasks()[1].stdout(0).unwrap()).unwrap();
+    let (run_dir, listing) = stdout.split_once('\n').unwrap();
+    assert_eq!(listing, "0.outputs\ndump\ndump.tar 42\n");
+
+    let outputs = group.tasks()[0].outputs(0);
+    assert_eq!(outputs.get("file").map(|x| x.as_str()), Some("dump.tar"));
+    assert_eq!(outputs.get("ROWS").map(|x| x.as_str()), Some("42"));
+
+    /* Only the server may list the runs */
+    let run_dir = std::path::Path::new(run_dir);
+    let mode = run_dir.parent().unwrap().metadata().unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o711);
+
+    group.update();
+    assert!(!run_dir.exists());
+}
+
+#[test]
+fn test_outputs_symlink() {
+    let secret = std::env::temp_dir().join("scheduler-test-outputs-secret");
+    std::fs::write(&secret, "PASSWORD=hunter2\n").unwrap();
+
+    let conf = group("outputs-symlink", json!([
+        task("link", &format!("ln -s {} \"$SCHEDULER_OUTPUTS\"", secret.display()), None)
+    ]));
+    let mut group = TaskGroup::from(conf);
+
+    assert!(wait_for(&mut group, 0));
+    assert!(group.tasks()[0].outputs(0).is_empty());
+    std::fs::remove_file(secret).unwrap();
+}
+
+#[test]
+fn test_depends_on_validation() {
+    let conf = group("invalid", json!([
+        {"name": "a", "depends_on": ["b"], "cmd": {"script": "true", "chdir": "/"}},
+        {"name": "b", "depends_on": ["a"], "cmd": {"script": "true", "chdir": "/"}},
+    ]));
+    assert!(conf.validate().unwrap_err().contains("Cycle"));
+
+    let conf = group("invalid", json!([
+        {"name": "a", "cmd": {"script": "true", "chdir": "/"}},
+        {"name": "b", "depends_on": ["a"], "cmd": {"script": "true", "chdir": "/"}},
+        {"name": "c", "depends_on": ["a", "b"], "cmd": {"script": "echo {{output.x}}", "chdir": "/"}},
+    ]));
+    conf.validate().unwrap();
+}
+
+#[test]
+fn test_max_runs() {
+    let mut conf = serde_json::to_value(group("limited", json!([task("a", "true", None)]))).unwrap();
+    conf["max_runs"] = json!(2);
+    conf["expire_action"] = json!("remove");
+    let mut group: TaskGroup = serde_json::from_value(conf).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !group.is_finished() && Instant::now() < deadline {
+        group.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(group.is_expired());
+    assert!(group.is_finished());
+    assert!(group.take_dirty());
+    assert_eq!(group.runs(), 2);
+    assert_eq!(group.next_execution(), None);
+
+    /* The state is kept when the group is saved */
+    let saved = serde_json::to_value(&group).unwrap();
+    assert_eq!(saved["runs"], json!(2));
+    assert_eq!(saved["expired"], json!(true));
+    let group: TaskGroup = serde_json::from_value(saved).unwrap();
+    assert!(group.is_expired());
+    assert_eq!(group.next_execution(), None);
+}
+
+#[test]
+fn test_one_shot_removed() {
+    let mut conf = serde_json::to_value(group("once", json!([task("a", "true", None)]))).unwrap();
+    let start = Utc::now() + chrono::Duration::seconds(1);
+    conf["starts_at"] = json!(start.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+    conf["period"] = serde_json::Value::Null;
+    conf["expire_action"] = json!("remove");
+    let mut group: TaskGroup = serde_json::from_value(conf).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !group.is_finished() && Instant::now() < deadline {
+        group.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(group.is_finished());
+    assert_eq!(group.runs(), 1);
+}
+
+#[test]
+fn test_ends_at() {
+    let mut conf = serde_json::to_value(group("ended", json!([task("a", "true", None)]))).unwrap();
+    conf["ends_at"] = json!("2020-01-01T00:00:00Z");
+    let mut group: TaskGroup = serde_json::from_value(conf.clone()).unwrap();
+
+    group.update();
+    assert!(group.is_expired());
+    assert!(!group.is_finished());
+    assert_eq!(group.runs(), 0);
+    assert_eq!(group.tasks()[0].iter().count(), 0);
+
+    conf["ends_at"] = json!("tomorrow");
+    assert!(serde_json::from_value::<SerializedTaskGroup>(conf.clone()).unwrap().validate().is_err());
+    conf["ends_at"] = json!(null);
+    conf["max_runs"] = json!(0);
+    assert!(serde_json::from_value::<SerializedTaskGroup>(conf).unwrap().validate().is_err());
+}
+
+#[test]
+fn test_jitter() {
+    let conf = |deterministic: bool| serde_json::from_value::<TaskGroup>(json!({
+        "name": "jittered",
+        "starts_at": "2099-01-01T00:00:00Z",
+        "period": "0000-00-01 00:00:00",
+        "jitter": 3600,
+        "deterministic_jitter": deterministic,
+        "processes": [task("a", "true", None)]
+    })).unwrap();
+
+    let start = Utc.with_ymd_and_hms(2099, 1, 1, 0, 0, 0).unwrap();
+    let next = conf(true).next_execution().unwrap();
+    assert!(next >= start && next <= start + chrono::Duration::hours(1));
+    assert_eq!(conf(true).next_execution(), Some(next));
+
+    let next = conf(false).next_execution().unwrap();
+    assert!(next >= start && next <= start + chrono::Duration::hours(1));
+}
+
+#[test]
+fn test_spread() {
+    let mut conf = serde_json::to_value(group("spread", json!([
+        task("a", "echo $SCHEDULER_SCHEDULED_TIME $SCHEDULER_FIRE_TIME", None),
+        task("b", "echo $SCHEDULER_SCHEDULED_TIME $SCHEDULER_FIRE_TIME", None),
+    ]))).unwrap();
+    conf["spread"] = json!(2);
+    let mut group: TaskGroup = serde_json::from_value(conf).unwrap();
+
+    assert!(wait_for(&mut group, 1));
+    let times = |id: usize| -> Vec<DateTime<Utc>> {
+        String::from_utf8(group.tasks()[id].stdout(0).unwrap()).unwrap()
+            .split_whitespace()
+            .map(|x| DateTime::parse_from_rfc3339(x).unwrap().to_utc())
+            .collect()
+    };
+
+    let (a, b) = (times(0), times(1));
+    let outcome = group.tasks()[1].iter().next().unwrap().outcome().unwrap();
+    assert_eq!((outcome.scheduled_time, outcome.fire_time), (b[0], b[1]));
+    assert_eq!(a[0], b[0]);
+    assert!(a[1] >= a[0] && a[1] - a[0] < chrono::Duration::seconds(1));
+    /* b is started a second after the occurrence was launched */
+    assert!(b[1] - a[0] >= chrono::Duration::seconds(1));
+}
+
+#[test]
+fn test_run_when() {
+    let mut conf = serde_json::to_value(group("batch", json!([task("a", "echo done", None)]))).unwrap();
+    /* Hardly ever idle enough, the execution runs after its maximum delay */
+    conf["processes"][0]["run_when"] = json!({"max_loadavg": 0.0, "max_cpu_pressure": 0.0, "max_delay": 1});
+    let mut group: TaskGroup = serde_json::from_value(conf).unwrap();
+
+    assert!(wait_for(&mut group, 0));
+    assert_eq!(group.tasks()[0].stdout(0).unwrap(), b"done\n");
+}
+
+/* Where the tests can create cgroups, if anywhere */
+fn cgroup_root() -> Option<std::path::PathBuf> {
+    let root = ["/sys/fs/cgroup", "/sys/fs/cgroup/unified"].iter()
+        .map(std::path::PathBuf::from)
+        .find(|x| x.join("cgroup.controllers").exists())?;
+    let path = root.join(format!("scheduler-test-{}", std::process::id()));
+    std::fs::create_dir(&path).ok()?;
+    Some(path)
+}
+
+#[test]
+fn test_take_over() {
+    let dir = std::env::temp_dir().join(format!("scheduler-take-over-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cgroup = cgroup_root();
+    let script = |delay: &str| format!("grep '^0::' /proc/self/cgroup; sleep {}", delay);
+    let configure = |group: &mut TaskGroup| {
+        group.set_log_path(dir.join("replaced"));
+        if let Some(path) = &cgroup {
+            group.set_cgroup_path(path.join("replaced"));
+        }
+    };
+
+    let mut old: TaskGroup = group("replaced", json!([task("a", &script("3"), None)])).into();
+    configure(&mut old);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while old.runs() == 0 && Instant::now() < deadline {
+        old.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(old.runs(), 1);
+
+    let mut new: TaskGroup = group("replaced", json!([task("a", &script("0"), None)])).into();
+    configure(&mut new);
+    new.take_over(&mut old);
+    assert!(old.is_expired());
+    assert_eq!(new.runs(), old.runs());
+
+    /* The new version runs while the old one is still running, in other
+     * cgroups, and with other logs */
+    assert!(wait_for(&mut new, 0));
+    old.update();
+    assert!(!old.is_finished());
+
+    /* The old version finishes what it started, and nothing more */
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !old.is_finished() && Instant::now() < deadline {
+        old.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(old.is_finished());
+    assert_eq!(old.runs(), 1);
+    assert!(matches!(old.tasks()[0].iter().next(), Some(TaskOutput::NoError(_))));
+    assert!(matches!(new.tasks()[0].iter().next(), Some(TaskOutput::NoError(_))));
+
+    let log = |n: usize| std::fs::read_to_string(dir.join(format!("replaced/0/out/{}", n))).unwrap();
+    if cgroup.is_some() {
+        assert!(log(0).trim().ends_with("/replaced/0/0"), "{}", log(0));
+        assert!(log(1).trim().ends_with("/replaced/0/1"), "{}", log(1));
+    } else {
+        assert_ne!(log(0), "");
+        assert_ne!(log(1), "");
+    }
+
+    if let Some(path) = &cgroup {
+        for x in ["replaced/0", "replaced", ""] {
+            std::fs::remove_dir(path.join(x)).unwrap();
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
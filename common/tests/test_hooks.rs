@@ -0,0 +1,272 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write}, net::TcpListener, os::unix::fs::PermissionsExt, thread, time::{Duration, Instant}
+};
+
+use common::{
+    command::TaskOutput, env::RunContext, hooks::{Hook, HookEvent, RunDetails}, notify::{SmtpRelay, Url}, secrets::SecretStore, task::{Task, TaskConfig}
+};
+use serde_json::json;
+
+fn details() -> RunDetails {
+    RunDetails {
+        event: Some(HookEvent::Failure),
+        group: String::from("backup"),
+        task: String::from("dump"),
+        run_id: 4,
+        attempt: 2,
+        status: String::from("NoError"),
+        exit_code: Some(1),
+        stderr_tail: String::from("pg_dump: error\n.hidden"),
+        ..RunDetails::default()
+    }
+}
+
+/* Accepts a single HTTP request, answers with the given status line and
+ * returns the request.
+ */
+fn http_server(status: &'static str) -> (u16, thread::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    (port, thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(x) = line.strip_prefix("Content-Length: ") {
+                length = x.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8(body).unwrap());
+
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+        request
+    }))
+}
+
+/* Plays the part of a SMTP relay for a single mail, returning the
+ * commands and the data it received.
+ */
+fn smtp_server() -> (u16, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    (port, thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut received = Vec::new();
+        let mut in_data = false;
+
+        write!(stream, "220 localhost ESMTP\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end_matches("\r\n").to_string();
+            received.push(line.clone());
+
+            let answer =
+                if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 Queued"
+                } else if line == "DATA" {
+                    in_data = true;
+                    "354 Go ahead"
+                } else if line == "QUIT" {
+                    "221 Bye"
+                } else {
+                    "250-localhost\r\n250 OK"
+                };
+            write!(stream, "{}\r\n", answer).unwrap();
+            if line == "QUIT" {
+                break;
+            }
+        }
+        received
+    }))
+}
+
+#[test]
+fn test_url_parse() {
+    assert_eq!(
+        Url::parse("http://localhost:8080/hooks/a?b=c"),
+        Ok(Url { host: String::from("localhost"), port: 8080, path: String::from("/hooks/a?b=c") })
+    );
+    assert_eq!(
+        Url::parse("http://example.org"),
+        Ok(Url { host: String::from("example.org"), port: 80, path: String::from("/") })
+    );
+    assert!(Url::parse("https://example.org").is_err());
+}
+
+#[test]
+fn test_webhook() {
+    let (port, server) = http_server("200 OK");
+    let hook: Hook = serde_json::from_value(json!({
+        "webhook": {
+            "url": format!("http://127.0.0.1:{}/notify", port),
+            "headers": {"X-Token": "abc"},
+            "message": "{{group}}/{{task}} failed ({{exit_code}})"
+        }
+    })).unwrap();
+
+    hook.run(&details(), None, None).unwrap();
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with("POST /notify HTTP/1.1\r\n"));
+    assert!(request.contains("X-Token: abc\r\n"));
+    let body: serde_json::Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["message"], "backup/dump failed (1)");
+    assert_eq!(body["event"], "failure");
+    assert_eq!(body["run_id"], 4);
+}
+
+#[test]
+fn test_webhook_error() {
+    let (port, server) = http_server("500 Internal Server Error");
+    let hook: Hook = serde_json::from_value(json!({
+        "webhook": {"url": format!("http://127.0.0.1:{}/", port)}
+    })).unwrap();
+
+    assert!(hook.run(&details(), None, None).is_err());
+    server.join().unwrap();
+}
+
+#[test]
+fn test_mail() {
+    let (port, server) = smtp_server();
+    let relay = SmtpRelay {
+        host: String::from("127.0.0.1"),
+        port,
+        from: String::from("scheduler@example.org"),
+        hello: String::from("test"),
+    };
+    let hook: Hook = serde_json::from_value(json!({
+        "mail": {"to": ["ops@example.org"]}
+    })).unwrap();
+
+    hook.run(&details(), Some(&relay), None).unwrap();
+    let received = server.join().unwrap();
+
+    assert_eq!(&received[.. 4], [
+        "HELO test",
+        "MAIL FROM:<scheduler@example.org>",
+        "RCPT TO:<ops@example.org>",
+        "DATA",
+    ]);
+    assert!(received.contains(&String::from("Subject: [scheduler] backup/dump: failure")));
+    assert!(received.contains(&String::from("pg_dump: error")));
+    assert!(received.contains(&String::from("..hidden")));
+    assert_eq!(received.last().unwrap(), "QUIT");
+}
+
+#[test]
+fn test_command_hook() {
+    let path = std::env::temp_dir().join("scheduler-test-hook.json");
+    let _ = std::fs::remove_file(&path);
+    let hook: Hook = serde_json::from_value(json!({
+        "command": {
+            "program": "/bin/sh",
+            "args": ["-c", format!("cat > {}", path.display())],
+            "chdir": "/"
+        }
+    })).unwrap();
+
+    hook.run(&details(), None, None).unwrap();
+    let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(written["task"], "dump");
+    assert_eq!(written["attempt"], 2);
+}
+
+#[test]
+fn test_command_hook_secrets() {
+    let dir = std::env::temp_dir().join(format!("scheduler-test-hook-secrets-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+    std::fs::write(dir.join("token"), b"s3cr3t").unwrap();
+    std::fs::set_permissions(dir.join("token"), std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let out = dir.join("out");
+    let hook: Hook = serde_json::from_value(json!({
+        "command": {
+            "program": "/bin/sh",
+            "args": ["-c", format!("printf %s \"$TOKEN\" > {}", out.display())],
+            "envs": {"TOKEN": {"secret": "token"}},
+            "chdir": "/"
+        }
+    })).unwrap();
+
+    assert!(hook.run(&details(), None, None).is_err());
+    hook.run(&details(), None, Some(&SecretStore::Directory(dir.clone()))).unwrap();
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "s3cr3t");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_retries() {
+    let conf: TaskConfig = serde_json::from_value(json!({
+        "cmd": {"program": "/bin/sh", "args": ["-c", "exit 1"], "chdir": "/"},
+        "retries": 2
+    })).unwrap();
+    let mut task = Task::new(conf);
+    task.run(RunContext { attempt: 1, ..RunContext::default() });
+
+    let mut events = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while events.len() < 4 && Instant::now() < deadline {
+        task.update();
+        events.extend(task.take_events());
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let kinds: Vec<(HookEvent, u32)> = events.iter()
+        .map(|(event, details)| (*event, details.attempt))
+        .collect();
+    assert_eq!(kinds, vec![
+        (HookEvent::Failure, 1),
+        (HookEvent::Failure, 2),
+        (HookEvent::Failure, 3),
+        (HookEvent::RetryExhausted, 3),
+    ]);
+    assert_eq!(task.iter().filter(|x| matches!(x, TaskOutput::NoError(_))).count(), 3);
+}
+
+/* Without retries, only the failure is reported */
+#[test]
+fn test_no_retries() {
+    let conf: TaskConfig = serde_json::from_value(json!({
+        "cmd": {"program": "/bin/sh", "args": ["-c", "exit 1"], "chdir": "/"}
+    })).unwrap();
+    let mut task = Task::new(conf);
+    task.run(RunContext { attempt: 1, ..RunContext::default() });
+
+    let mut events = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while task.take_finished_runs().is_empty() && Instant::now() < deadline {
+        task.update();
+        events.extend(task.take_events());
+        thread::sleep(Duration::from_millis(10));
+    }
+    events.extend(task.take_events());
+
+    let kinds: Vec<HookEvent> = events.iter()
+        .map(|(event, _)| *event)
+        .collect();
+    assert_eq!(kinds, vec![HookEvent::Failure]);
+}
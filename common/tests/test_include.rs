@@ -0,0 +1,54 @@
+use std::fs;
+
+use common::{group::Provenance, include};
+use serde_json::json;
+
+fn group(name: &str) -> serde_json::Value {
+    json!({
+        "name": name,
+        "starts_at": "2099-01-01T00:00:00Z",
+        "period": null,
+        "processes": [{"cmd": {"script": "true", "chdir": "/"}}]
+    })
+}
+
+#[test]
+fn test_include() {
+    let dir = std::env::temp_dir().join(format!("scheduler-include-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("b.json"), json!([group("b1"), group("b2")]).to_string()).unwrap();
+    fs::write(dir.join("a.json"), group("a").to_string()).unwrap();
+    fs::write(dir.join(".hidden.json"), group("hidden").to_string()).unwrap();
+    fs::write(dir.join("c.conf"), group("c").to_string()).unwrap();
+    fs::write(dir.join(".d.conf"), group("d").to_string()).unwrap();
+
+    /* Every .json file of the directory, in the lexical order */
+    let groups = include::load(&dir).unwrap();
+    let names: Vec<&str> = groups.iter().map(|x| x.name()).collect();
+    assert_eq!(names, ["a", "b1", "b2"]);
+    assert_eq!(*groups[0].provenance(), Provenance::Include(dir.join("a.json")));
+    assert_eq!(*groups[2].provenance(), Provenance::Include(dir.join("b.json")));
+
+    /* Or the files matching a pattern */
+    let groups = include::load(&dir.join("*.conf")).unwrap();
+    let names: Vec<&str> = groups.iter().map(|x| x.name()).collect();
+    assert_eq!(names, ["c"]);
+    let groups = include::load(&dir.join(".*.conf")).unwrap();
+    let names: Vec<&str> = groups.iter().map(|x| x.name()).collect();
+    assert_eq!(names, ["d"]);
+    assert!(include::load(&dir.join("*.none")).unwrap().is_empty());
+
+    /* A broken file stops the loading, with its name */
+    fs::write(dir.join("e.json"), json!({"name": "e"}).to_string()).unwrap();
+    assert!(include::load(&dir).unwrap_err().contains("e.json"));
+    let mut invalid = group("f");
+    invalid["max_runs"] = json!(0);
+    fs::write(dir.join("e.json"), invalid.to_string()).unwrap();
+    assert!(include::load(&dir).unwrap_err().contains("e.json"));
+
+    assert!(include::load(&dir.join("missing/*.json")).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,38 @@
+use common::load::{parse_loadavg, parse_pressure, RunWhen, SystemLoad};
+use serde_json::json;
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse_loadavg("0.52 0.58 0.59 1/467 12345\n"), Some(0.52));
+    assert_eq!(parse_loadavg(""), None);
+
+    let pressure = "some avg10=1.53 avg60=0.87 avg300=0.27 total=5309455\n\
+        full avg10=0.50 avg60=0.20 avg300=0.05 total=2153311\n";
+    assert_eq!(parse_pressure(pressure), Some(1.53));
+    assert_eq!(parse_pressure("full avg10=0.50 avg60=0.20 avg300=0.05 total=1\n"), None);
+}
+
+#[test]
+fn test_run_when() {
+    let run_when: RunWhen = serde_json::from_value(json!({
+        "max_loadavg": 2.0,
+        "max_io_pressure": 10.0,
+        "max_delay": 3600
+    })).unwrap();
+    assert!(run_when.validate().is_ok());
+
+    let load = SystemLoad {
+        loadavg: Some(1.5),
+        cpu_pressure: Some(90.),
+        memory_pressure: None,
+        io_pressure: Some(5.),
+    };
+    assert!(run_when.is_met(&load));
+    assert!(!run_when.is_met(&SystemLoad { loadavg: Some(2.5), ..load }));
+    assert!(!run_when.is_met(&SystemLoad { io_pressure: Some(10.5), ..load }));
+    /* Without PSI */
+    assert!(run_when.is_met(&SystemLoad { io_pressure: None, ..load }));
+
+    assert!(serde_json::from_value::<RunWhen>(json!({"max_delay": 60})).unwrap().validate().is_err());
+    assert!(serde_json::from_value::<RunWhen>(json!({"max_loadavg": -1.0})).unwrap().validate().is_err());
+}
@@ -0,0 +1,70 @@
+use std::{thread, time::{Duration, Instant}};
+
+use common::{env::RunContext, metrics::{Exposition, Histogram}, task::{Task, TaskConfig}};
+use serde_json::json;
+
+#[test]
+fn test_histogram() {
+    static BOUNDS: [f64; 3] = [1., 5., 10.];
+    let mut histogram = Histogram::new(&BOUNDS);
+    for value in [0.5, 1., 3., 20.] {
+        histogram.observe(value);
+    }
+
+    let buckets: Vec<(f64, u64)> = histogram.buckets().collect();
+    assert_eq!(buckets, vec![(1., 2), (5., 3), (10., 3), (f64::INFINITY, 4)]);
+    assert_eq!(histogram.sum(), 24.5);
+    assert_eq!(histogram.count(), 4);
+}
+
+#[test]
+fn test_exposition() {
+    static BOUNDS: [f64; 1] = [0.5];
+    let mut histogram = Histogram::new(&BOUNDS);
+    histogram.observe(0.25);
+
+    let mut out = Exposition::new();
+    out.family("jobs_total", "counter", "Jobs");
+    out.sample("jobs_total", &[("group", "a \"b\"\\c")], 3.);
+    out.sample("jobs_total", &[], 1.5);
+    out.family("job_seconds", "histogram", "Duration");
+    out.histogram("job_seconds", &[("task", "t")], &histogram);
+
+    assert_eq!(out.finish(), "\
+# HELP jobs_total Jobs
+# TYPE jobs_total counter
+jobs_total{group=\"a \\\"b\\\"\\\\c\"} 3
+jobs_total 1.5
+# HELP job_seconds Duration
+# TYPE job_seconds histogram
+job_seconds_bucket{task=\"t\",le=\"0.5\"} 1
+job_seconds_bucket{task=\"t\",le=\"+Inf\"} 1
+job_seconds_sum{task=\"t\"} 0.25
+job_seconds_count{task=\"t\"} 1
+");
+}
+
+#[test]
+fn test_task_metrics() {
+    let conf: TaskConfig = serde_json::from_value(json!({
+        "cmd": {"program": "/bin/sh", "args": ["-c", "sleep 0.2; exit 1"], "chdir": "/"},
+        "max_concurrent_execution": 1
+    })).unwrap();
+    let mut task = Task::new(conf);
+    task.run(RunContext { attempt: 1, ..RunContext::default() });
+    task.run(RunContext { attempt: 1, ..RunContext::default() });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while task.nb_running_tasks() > 0 && Instant::now() < deadline {
+        task.update();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let metrics = task.metrics();
+    assert_eq!(metrics.runs, 1);
+    assert_eq!(metrics.failures, 1);
+    assert_eq!(metrics.skipped, 1);
+    assert_eq!(metrics.timeouts, 0);
+    assert_eq!(metrics.duration.count(), 1);
+    assert!(task.last_success().is_none());
+}
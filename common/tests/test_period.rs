@@ -0,0 +1,126 @@
+use chrono::{DateTime, TimeZone, Utc};
+use common::utils::get_period_from_string;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap()
+}
+
+fn next(period: &str) -> Option<DateTime<Utc>> {
+    get_period_from_string(period)?.nth(start(), 1)
+}
+
+fn after(sec: i64, nanos: i64) -> Option<DateTime<Utc>> {
+    Some(start() + chrono::Duration::seconds(sec) + chrono::Duration::nanoseconds(nanos))
+}
+
+#[test]
+fn test_fixed_width() {
+    assert_eq!(next("0000-00-00 00:01:00"), after(60, 0));
+    assert_eq!(next("0000-00-01 01:00:30"), after(90_030, 0));
+    assert_eq!(next("0000-01-00 00:00:00"), Some(Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap()));
+    assert_eq!(next("0000-00-00 00:00:00.25"), after(0, 250_000_000));
+    assert_eq!(next("0000-00-00 00:01:0"), None);
+    assert_eq!(next("0000-00-00 00:00:00"), None);
+}
+
+#[test]
+fn test_iso8601() {
+    assert_eq!(next("PT1M"), after(60, 0));
+    assert_eq!(next("P1DT12H"), after(129_600, 0));
+    assert_eq!(next("P2W"), after(14 * 86_400, 0));
+    assert_eq!(next("PT0.5S"), after(0, 500_000_000));
+    assert_eq!(next("P1Y"), Some(Utc.with_ymd_and_hms(2025, 1, 31, 12, 0, 0).unwrap()));
+    assert_eq!(next("P1M"), Some(Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap()));
+    assert_eq!(next("P"), None);
+    assert_eq!(next("PT"), None);
+    assert_eq!(next("P1H"), None);
+    assert_eq!(next("PT1M1H"), None);
+    assert_eq!(next("P1.5D"), None);
+    assert_eq!(next("PT0S"), None);
+}
+
+#[test]
+fn test_compact() {
+    assert_eq!(next("90s"), after(90, 0));
+    assert_eq!(next("1h30m"), after(5400, 0));
+    assert_eq!(next("1 hour 30 minutes"), after(5400, 0));
+    assert_eq!(next("every 2 weeks"), after(14 * 86_400, 0));
+    assert_eq!(next("every day"), after(86_400, 0));
+    assert_eq!(next("1500ms"), after(1, 500_000_000));
+    assert_eq!(next("2.5s"), after(2, 500_000_000));
+    assert_eq!(next("1mo"), Some(Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap()));
+    assert_eq!(next("day"), None);
+    assert_eq!(next("10 fortnights"), None);
+    assert_eq!(next("1.5h"), None);
+    assert_eq!(next("0s"), None);
+    assert_eq!(next(""), None);
+
+    /* Amounts that don't fit are refused */
+    assert!(get_period_from_string("99999999999999ms").is_none());
+    assert!(get_period_from_string("9223372036854775807s 1s").is_none());
+    assert!(get_period_from_string("9223372036854775807s 1000ms").is_none());
+    assert!(get_period_from_string("4294967295mo 1mo").is_none());
+}
+
+#[test]
+fn test_display() {
+    let display = |x: &str| get_period_from_string(x).unwrap().to_string();
+    assert_eq!(display("0000-00-01 00:01:00"), "P1DT1M");
+    assert_eq!(display("every 2 weeks"), "P2W");
+    assert_eq!(display("1500ms"), "PT1.5S");
+    assert_eq!(display("P1Y2M"), "P1Y2M");
+}
+
+#[test]
+fn test_month_end() {
+    let monthly = get_period_from_string("P1M").unwrap();
+    let days: Vec<String> = (0 .. 5)
+        .map(|k| monthly.nth(start(), k).unwrap().format("%m-%d").to_string())
+        .collect();
+    assert_eq!(days, vec!["01-31", "02-29", "03-31", "04-30", "05-31"]);
+
+    let time = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+    assert_eq!(
+        monthly.occurrence_from(start(), time),
+        Some(Utc.with_ymd_and_hms(2024, 4, 30, 12, 0, 0).unwrap())
+    );
+    let time = Utc.with_ymd_and_hms(2024, 4, 30, 12, 0, 0).unwrap();
+    assert_eq!(monthly.occurrence_from(start(), time), Some(time));
+}
+
+#[test]
+fn test_occurrence_from() {
+    let period = get_period_from_string("1s").unwrap();
+    let anchor = Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap();
+    let time = Utc.with_ymd_and_hms(2024, 6, 1, 10, 20, 30).unwrap();
+    assert_eq!(period.occurrence_from(anchor, time), Some(time));
+    assert_eq!(
+        period.occurrence_from(anchor, time + chrono::Duration::milliseconds(1)),
+        Some(time + chrono::Duration::seconds(1))
+    );
+    assert_eq!(period.occurrence_from(anchor, anchor - chrono::Duration::days(1)), Some(anchor));
+
+    let period = get_period_from_string("P1M1D").unwrap();
+    let next = period.occurrence_from(anchor, time).unwrap();
+    assert!(next >= time);
+    assert!(next - time <= chrono::Duration::days(32));
+    let k = (0 ..).find(|k| period.nth(anchor, *k).unwrap() == next).unwrap();
+    assert!(period.nth(anchor, k - 1).unwrap() < time);
+
+    let yearly = get_period_from_string("every year").unwrap();
+    let leap = Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap();
+    assert_eq!(yearly.nth(leap, 1), Some(Utc.with_ymd_and_hms(2025, 2, 28, 0, 0, 0).unwrap()));
+    assert_eq!(yearly.nth(leap, 4), Some(Utc.with_ymd_and_hms(2028, 2, 29, 0, 0, 0).unwrap()));
+}
+
+#[test]
+fn test_overflow() {
+    let period = get_period_from_string("every 99999999999999 weeks").unwrap();
+    assert_eq!(period.nth(start(), 1), None);
+    assert_eq!(period.occurrence_from(start(), start() + chrono::Duration::days(1)), None);
+
+    let period = get_period_from_string("1s").unwrap();
+    assert_eq!(period.nth(start(), u64::MAX), None);
+    let period = get_period_from_string("4294967295y").unwrap();
+    assert_eq!(period.nth(start(), 1), None);
+}
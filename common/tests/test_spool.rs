@@ -0,0 +1,75 @@
+use std::{fs, path::Path};
+
+use common::spool::Spool;
+use serde_json::json;
+
+fn group(name: &str) -> String {
+    json!({
+        "name": name,
+        "starts_at": "2099-01-01T00:00:00Z",
+        "period": null,
+        "processes": [{"cmd": {"script": "true", "chdir": "/"}}]
+    }).to_string()
+}
+
+/* Reads the files as the server does, rejecting the groups named "bad" */
+fn process(spool: &mut Spool) -> Vec<String> {
+    let mut out = Vec::new();
+    for path in spool.take_files() {
+        let result = Spool::read(&path)
+            .and_then(|stg| match stg.name() {
+            "bad" => Err(String::from("refused")),
+            name => Ok(name.to_owned())
+            });
+        out.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        spool.done(&path, result.map(|_| ()));
+    }
+    out
+}
+
+#[test]
+fn test_spool() {
+    let dir = std::env::temp_dir().join(format!("scheduler-spool-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    /* Dropped while the server was down */
+    fs::write(dir.join("b.json"), group("b")).unwrap();
+    fs::write(dir.join("a.json"), group("a")).unwrap();
+    fs::write(dir.join(".hidden.json"), group("hidden")).unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+
+    let mut spool = Spool::new(dir.clone()).unwrap();
+    assert_eq!(process(&mut spool), ["a.json", "b.json"]);
+    assert!(process(&mut spool).is_empty());
+
+    /* Written elsewhere, then moved in */
+    fs::write(dir.join(".c.tmp"), group("c")).unwrap();
+    fs::rename(dir.join(".c.tmp"), dir.join("c.json")).unwrap();
+    fs::write(dir.join("d.json"), group("bad")).unwrap();
+    fs::write(dir.join("e.json"), "{").unwrap();
+    assert_eq!(process(&mut spool), ["c.json", "d.json", "e.json"]);
+
+    let listing = |sub: &str| {
+        let mut names: Vec<String> = fs::read_dir(dir.join(sub)).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(listing("processed"), ["a.json", "b.json", "c.json"]);
+    assert_eq!(listing("rejected"), ["d.json", "d.json.error", "e.json", "e.json.error"]);
+    assert_eq!(fs::read_to_string(dir.join("rejected/d.json.error")).unwrap(), "refused\n");
+    assert!(!fs::read_to_string(dir.join("rejected/e.json.error")).unwrap().is_empty());
+
+    /* Only what was not submitted is left */
+    let left: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    assert_eq!(left.len(), 2);
+    assert!(left.iter().all(|path| [".hidden.json", "notes.txt"].iter()
+        .any(|name| path.file_name().unwrap() == Path::new(name).as_os_str())));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
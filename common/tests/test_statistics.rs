@@ -0,0 +1,104 @@
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus, time::{Duration, Instant}};
+
+use chrono::{TimeZone, Utc};
+use common::{
+    command::{CommandOutcome, Log, ResourceUsage, TaskOutput}, metrics::QuantileSketch, task::TaskStatistic
+};
+
+/* Raw wait statuses: the exit code is in the second byte */
+fn outcome(status: i32, duration_ms: u64) -> CommandOutcome {
+    CommandOutcome {
+        exit_status: ExitStatus::from_raw(status),
+        stdout: Log::Buffer(Vec::new()),
+        stderr: Log::Buffer(Vec::new()),
+        start: Instant::now(),
+        duration: Duration::from_millis(duration_ms),
+        usage: ResourceUsage::default(),
+        success: status == 0,
+        script_hash: None,
+        outputs: Default::default(),
+        scheduled_time: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+        fire_time: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 3).unwrap()
+    }
+}
+
+#[test]
+fn test_sketch() {
+    let mut sketch = QuantileSketch::default();
+    assert_eq!(sketch.quantile(0.5), None);
+
+    for i in 1 ..= 1000 {
+        sketch.insert(i as f64);
+    }
+
+    assert_eq!(sketch.min(), Some(1.));
+    assert_eq!(sketch.max(), Some(1000.));
+    for (q, expected) in [(0.5, 500.), (0.95, 950.), (0.99, 990.)] {
+        let value = sketch.quantile(q).unwrap();
+        assert!((value - expected).abs() <= 0.02 * expected, "p{} = {}", q, value);
+    }
+}
+
+#[test]
+fn test_empty() {
+    let summary = TaskStatistic::default().summary();
+    assert_eq!(summary.count, 0);
+    assert_eq!(summary.average_duration, None);
+
+    let text = summary.to_string();
+    assert!(text.contains("Error rate: n/a"));
+    assert!(!text.contains("NaN"));
+}
+
+#[test]
+fn test_outcomes() {
+    let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+    let mut stats = TaskStatistic::default();
+
+    stats.record(&TaskOutput::NoError(outcome(0, 100)), start);
+    stats.record(&TaskOutput::NoError(outcome(2 << 8, 200)), start);
+    stats.record(&TaskOutput::TimedOut(outcome(libc::SIGKILL, 300)), start);
+    stats.record(&TaskOutput::TooManyThreadsError, start);
+
+    let summary = stats.summary_at(start);
+    assert_eq!(summary.count, 4);
+    assert_eq!(summary.success_count, 1);
+    assert_eq!(summary.failure_count, 3);
+    assert_eq!(summary.timeout_count, 1);
+    assert_eq!(summary.skipped_count, 1);
+    assert_eq!(summary.exit_codes.clone().into_iter().collect::<Vec<_>>(), vec![(0, 1), (2, 1)]);
+    assert_eq!(summary.signals.clone().into_iter().collect::<Vec<_>>(), vec![(libc::SIGKILL, 1)]);
+    assert_eq!(summary.failure_streak, 3);
+    assert_eq!(summary.last_success, Some(start));
+    assert_eq!(summary.last_failure, Some(start));
+    assert_eq!(summary.last_scheduled_time, Some(start));
+    assert_eq!(summary.last_fire_time, Some(start + chrono::Duration::seconds(3)));
+
+    assert!((summary.average_duration.unwrap() - 0.2).abs() < 1e-9);
+    assert_eq!(summary.min_duration, Some(0.1));
+    assert_eq!(summary.max_duration, Some(0.3));
+    assert!(summary.to_string().contains("Error rate: 75.0%"));
+
+    stats.record(&TaskOutput::NoError(outcome(0, 100)), start);
+    assert_eq!(stats.summary_at(start).failure_streak, 0);
+}
+
+#[test]
+fn test_window() {
+    let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+    let mut stats = TaskStatistic::default();
+
+    stats.record(&TaskOutput::NoError(outcome(1 << 8, 1000)), start);
+    stats.record(&TaskOutput::NoError(outcome(0, 100)), start + chrono::Duration::hours(20));
+    stats.record(&TaskOutput::NoError(outcome(0, 300)), start + chrono::Duration::hours(30));
+
+    let window = stats.summary_at(start + chrono::Duration::hours(30)).window;
+    assert_eq!(window.count, 2);
+    assert_eq!(window.failure_count, 0);
+    assert!((window.average_duration.unwrap() - 0.2).abs() < 1e-9);
+    assert_eq!(window.max_duration, Some(0.3));
+
+    let window = stats.summary_at(start + chrono::Duration::hours(60)).window;
+    assert_eq!(window.count, 0);
+    assert_eq!(window.average_duration, None);
+}
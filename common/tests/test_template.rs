@@ -0,0 +1,80 @@
+use chrono::{TimeZone, Utc};
+use common::{env::RunContext, template::{render, validate}};
+
+fn context() -> RunContext {
+    RunContext {
+        group: String::from("backup"),
+        task: String::from("export"),
+        run_id: 12,
+        scheduled_time: Utc.with_ymd_and_hms(2026, 3, 1, 2, 30, 0).unwrap(),
+        attempt: 1,
+        ..RunContext::default()
+    }
+}
+
+#[test]
+fn test_render_0() {
+    assert_eq!(
+        render("/data/{{group}}/{{ scheduled_time:%Y-%m-%d }}.{{run_id}}", &context().template_variables()),
+        Ok(String::from("/data/backup/2026-03-01.12"))
+    )
+}
+
+#[test]
+fn test_render_1() {
+    assert_eq!(
+        render("{{scheduled_time}}", &context().template_variables()),
+        Ok(String::from("2026-03-01T02:30:00+00:00"))
+    )
+}
+
+#[test]
+fn test_render_2() {
+    let mut ctx = context();
+    assert_eq!(
+        render("since={{prev_success_time:%d}}", &ctx.template_variables()),
+        Ok(String::from("since="))
+    );
+
+    ctx.prev_success_time = Some(Utc.with_ymd_and_hms(2026, 2, 28, 2, 30, 0).unwrap());
+    assert_eq!(
+        render("since={{prev_success_time:%d}}", &ctx.template_variables()),
+        Ok(String::from("since=28"))
+    );
+}
+
+#[test]
+fn test_render_3() {
+    assert_eq!(
+        render("no placeholder { } }}", &context().template_variables()),
+        Ok(String::from("no placeholder { } }}"))
+    )
+}
+
+#[test]
+fn test_render_4() {
+    assert_eq!(
+        render("{{{{.Names}} {{{{group}} {{group}}", &context().template_variables()),
+        Ok(String::from("{{.Names}} {{group}} backup"))
+    )
+}
+
+#[test]
+fn test_validate_0() {
+    assert!(validate("{{unknown}}", &context().template_variables()).is_err())
+}
+
+#[test]
+fn test_validate_1() {
+    assert!(validate("{{group:%Y}}", &context().template_variables()).is_err())
+}
+
+#[test]
+fn test_validate_2() {
+    assert!(validate("{{scheduled_time:%Q}}", &context().template_variables()).is_err())
+}
+
+#[test]
+fn test_validate_3() {
+    assert!(validate("{{group", &context().template_variables()).is_err())
+}
@@ -214,3 +214,15 @@ fn test_get_start_timestamp_from_string_24() {
         Some(Utc.with_ymd_and_hms(now.year(), 12, 1, 12, 34, 12).unwrap())
     )
 }
+
+#[test]
+fn test_get_start_timestamp_from_string_25() {
+    /* Refused, without panicking */
+    for time in [
+        "", "2026-11-01T03:0", "2026-11-01T03:00:0", "2026-13-01T00:00:00Z",
+        "2026-02-30T00:00:00Z", "2026-11-01T24:00:00Z", "2026-11-01T03:00:00+1234:",
+        "2026-11-01T03:00:00+123:4", "2026-11-01T03:00:00é", "2026-11-0éT03:00:00Z"
+    ] {
+        assert_eq!(get_start_timestamp_from_string(time), None, "{}", time);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::HashMap, ffi::OsStr, fs, os::unix::ffi::OsStrExt, sync::Arc, thread, time::{Duration, Instant}};
+
+use common::{calendar::Calendar, command::TaskOutput, group::TaskGroup, watch::{glob_to_regex, FileTrigger}};
+use serde_json::json;
+
+#[test]
+fn test_glob() {
+    let glob = |pattern: &str, name: &str| glob_to_regex(pattern).unwrap().is_match(name);
+    assert!(glob("*.csv", "report.csv"));
+    assert!(!glob("*.csv", "report.csv.tmp"));
+    assert!(glob("data-?.txt", "data-1.txt"));
+    assert!(!glob("data-?.txt", "data-10.txt"));
+    assert!(glob("[ab]*", "b"));
+    assert!(!glob("[!ab]*", "b"));
+    assert!(glob("a+b(1).txt", "a+b(1).txt"));
+    assert!(glob_to_regex("[abc").is_err());
+}
+
+#[test]
+fn test_validation() {
+    let trigger = |value: serde_json::Value| serde_json::from_value::<FileTrigger>(value).unwrap();
+    assert!(trigger(json!({"path": "/tmp/*.csv"})).validate().is_ok());
+    assert!(trigger(json!({"path": "/tmp"})).validate().is_ok());
+    assert!(trigger(json!({"path": "incoming/*.csv"})).validate().is_err());
+    assert!(trigger(json!({"path": "/tmp/*/data.csv"})).validate().is_err());
+    assert!(trigger(json!({"path": "/tmp/*.csv", "events": []})).validate().is_err());
+}
+
+/* The file names come from whoever writes in the directory, so they are
+ * only given through the environment */
+fn group_with(dir: &std::path::Path, trigger: serde_json::Value, extra: serde_json::Value) -> TaskGroup {
+    let mut trigger = trigger;
+    trigger["path"] = json!(dir.join("*.csv"));
+    let mut conf = json!({
+        "name": "watcher",
+        "on_file": trigger,
+        "processes": [{
+            "name": "import",
+            "cmd": {"script": "printf '%s\\n' \"$SCHEDULER_TRIGGER_PATH\"", "chdir": "/"}
+        }]
+    });
+    for (k, v) in extra.as_object().unwrap() {
+        conf[k] = v.clone();
+    }
+    serde_json::from_value(conf).unwrap()
+}
+
+fn group(dir: &std::path::Path, trigger: serde_json::Value) -> TaskGroup {
+    group_with(dir, trigger, json!({}))
+}
+
+/* Updates the group for a while, and returns the outputs of its task */
+fn run_for(group: &mut TaskGroup, duration: Duration) -> Vec<String> {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        group.update();
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let task = &group.tasks()[0];
+    (0 .. task.iter().count())
+        .filter(|x| !matches!(task.iter().nth(*x), Some(TaskOutput::Waiting)))
+        .map(|x| String::from_utf8(task.stdout(x).unwrap()).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_on_file() {
+    let dir = std::env::temp_dir().join(format!("scheduler-watch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut watched = group(&dir, json!({}));
+
+    fs::write(dir.join("a.csv"), "a").unwrap();
+    fs::write(dir.join("b.txt"), "b").unwrap();
+    fs::write(dir.join(".hidden.csv"), "c").unwrap();
+    assert_eq!(run_for(&mut watched, Duration::from_millis(500)), vec![
+        format!("{}\n", dir.join("a.csv").display())
+    ]);
+    assert_eq!(watched.next_execution(), None);
+
+    /* Written several times, but quiet for a second once */
+    let mut debounced = group(&dir, json!({"debounce": 1}));
+    for _ in 0 .. 3 {
+        fs::write(dir.join("c.csv"), "c").unwrap();
+        debounced.update();
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(run_for(&mut debounced, Duration::from_millis(300)).is_empty());
+    assert_eq!(run_for(&mut debounced, Duration::from_millis(1000)).len(), 1);
+
+    let mut stable = group(&dir, json!({"stable_for": 1}));
+    fs::write(dir.join("d.csv"), "d").unwrap();
+    assert!(run_for(&mut stable, Duration::from_millis(300)).is_empty());
+    assert_eq!(run_for(&mut stable, Duration::from_millis(1200)).len(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_on_file_names() {
+    let dir = std::env::temp_dir().join(format!("scheduler-watch-names-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut watched = group(&dir, json!({}));
+
+    /* Not UTF-8, and with a quote */
+    fs::write(dir.join(OsStr::from_bytes(b"caf\xe9.csv")), "a").unwrap();
+    fs::write(dir.join("it's; true.csv"), "b").unwrap();
+    let mut outputs = run_for(&mut watched, Duration::from_millis(500));
+    outputs.sort();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[1], format!("{}\n", dir.join("it's; true.csv").display()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_on_file_calendars() {
+    let dir = std::env::temp_dir().join(format!("scheduler-watch-calendars-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let today = chrono::Utc::now().date_naive();
+    let mut freeze: Calendar = serde_json::from_value(json!({"ranges": [{
+        "from": (today - chrono::Duration::days(1)).to_string(),
+        "to": (today + chrono::Duration::days(2)).to_string()
+    }]})).unwrap();
+    freeze.load().unwrap();
+    let calendars = Arc::new(HashMap::from([(String::from("freeze"), freeze)]));
+
+    let mut skipped = group_with(&dir, json!({}), json!({"never_during": "freeze"}));
+    skipped.set_calendars(calendars.clone());
+    let mut jittered = group_with(&dir, json!({}), json!({"jitter": 3600, "deterministic_jitter": true}));
+
+    fs::write(dir.join("a.csv"), "a").unwrap();
+    assert!(run_for(&mut skipped, Duration::from_millis(500)).is_empty());
+    /* Delayed by the same amount as the occurrences of the group */
+    assert!(run_for(&mut jittered, Duration::from_millis(500)).is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,183 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use common::{at::{parse_time, AtJobSummary, AtRequest}, group::{SerializedTaskGroup, TaskGroup}};
+
+/* One-shot execution, run by a group of its own */
+#[derive(Debug)]
+pub struct AtJob {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub request: AtRequest,
+    pub group: TaskGroup,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SerializedAtJob {
+    id: u64,
+    time: DateTime<Utc>,
+    request: AtRequest,
+    #[serde(default)]
+    started: bool,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct SerializedAtQueue {
+    next_id: u64,
+    jobs: Vec<SerializedAtJob>,
+}
+
+/* The jobs waiting for their time, or running. They are saved to the
+ * file, if any, whenever the queue changes.
+ */
+#[derive(Debug, Default)]
+pub struct AtQueue {
+    path: Option<PathBuf>,
+    next_id: u64,
+    pub jobs: Vec<AtJob>,
+    dirty: bool,
+}
+
+fn job_name(id: u64, request: &AtRequest) -> String {
+    request.name.clone().unwrap_or(format!("at-{}", id))
+}
+
+impl AtJob {
+    /* Jobs whose time is past run as soon as possible */
+    fn new(id: u64, time: DateTime<Utc>, request: AtRequest) -> Self {
+        let start = time.max(Utc::now() + Duration::seconds(1));
+        let group = TaskGroup::from(SerializedTaskGroup::one_shot(
+            job_name(id, &request),
+            start,
+            request.within,
+            request.task()
+        ));
+        Self { id, time, request, group }
+    }
+
+    fn is_running(&self) -> bool {
+        self.group.runs() > 0 && !self.group.is_finished()
+    }
+
+    pub fn summary(&self) -> AtJobSummary {
+        AtJobSummary {
+            id: self.id,
+            name: job_name(self.id, &self.request),
+            time: self.time,
+            fire_time: self.group.next_execution(),
+            running: self.is_running(),
+            command: self.request.describe(),
+        }
+    }
+}
+
+impl AtQueue {
+    /* Jobs that were started before the server stopped are not run again */
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let conf: SerializedAtQueue = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            SerializedAtQueue::default()
+        };
+
+        let mut jobs = Vec::new();
+        for job in conf.jobs {
+            if job.started {
+                info!("[AT] Dropping job {}, interrupted while running", job.id);
+                continue;
+            }
+            jobs.push(AtJob::new(job.id, job.time, job.request));
+        }
+
+        Ok(Self {
+            path: Some(path),
+            next_id: conf.next_id,
+            jobs,
+            dirty: false,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn push(&mut self, request: AtRequest) -> Result<&mut AtJob, String> {
+        request.validate()?;
+        let time = parse_time(&request.time, Utc::now())
+            .ok_or_else(|| format!("Invalid time: {}", request.time))?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        info!("[AT] Job {} queued for {}: {}", id, time, request.describe());
+        self.jobs.push(AtJob::new(id, time, request));
+        self.dirty = true;
+        Ok(self.jobs.last_mut().unwrap())
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Result<(), String> {
+        let idx = self.jobs.iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| format!("No job {}", id))?;
+        if self.jobs[idx].group.runs() > 0 {
+            return Err(format!("Job {} already started", id));
+        }
+
+        info!("[AT] Job {} cancelled", id);
+        self.jobs.remove(idx);
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<AtJobSummary> {
+        self.jobs.iter()
+            .map(|job| job.summary())
+            .collect()
+    }
+
+    pub fn update(&mut self) {
+        for job in self.jobs.iter_mut() {
+            let started = job.group.runs();
+            job.group.update();
+            self.dirty |= job.group.runs() != started;
+        }
+
+        let len = self.jobs.len();
+        self.jobs.retain(|job| {
+            if job.group.is_finished() {
+                info!("[AT] Job {} done", job.id);
+            }
+            !job.group.is_finished()
+        });
+        self.dirty |= self.jobs.len() != len;
+
+        if self.dirty {
+            match self.save() {
+            Ok(()) => self.dirty = false,
+            Err(e) => error!("[AT] Unable to save the queue: {}", e)
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let conf = SerializedAtQueue {
+            next_id: self.next_id,
+            jobs: self.jobs.iter()
+                .map(|job| SerializedAtJob {
+                    id: job.id,
+                    time: job.time,
+                    request: job.request.clone(),
+                    started: job.group.runs() > 0,
+                })
+                .collect(),
+        };
+        fs::write(path, serde_json::to_string(&conf).unwrap())
+    }
+}
@@ -1,14 +1,33 @@
-use std::{fs, path::PathBuf};
+use std::{collections::{BTreeMap, HashMap}, fs, path::{Path, PathBuf}, sync::Arc};
 
 use log::{debug, error, info};
 
-use common::{group::TaskGroup};
+use common::{at::AtRequest, calendar::Calendars, cgroup, group::{GroupState, Provenance, SerializedTaskGroup, TaskGroup}, notify::SmtpRelay, secrets::SecretStore, spool::Spool};
 use serde::{Serialize, Serializer};
 
+use crate::at::AtQueue;
+
+/* Holds the directories of the groups, in the log and cgroup roots */
+const GROUPS_DIR: &str = "groups";
+
 #[derive(Debug)]
 pub struct Environment {
     pub groups: Vec<TaskGroup>,
     pub log: Option<PathBuf>,
+    pub cgroup: Option<PathBuf>,
+    pub secrets: Option<SecretStore>,
+    pub smtp: Option<SmtpRelay>,
+    pub calendars: Arc<Calendars>,
+    pub at: AtQueue,
+    pub spool: Option<Spool>,
+    /* Where the operators declare their own groups */
+    pub include: Option<PathBuf>,
+    /* State of the included groups which were removed, since their files
+     * still declare them */
+    pub included_state: BTreeMap<String, GroupState>,
+    /* Submitted groups replaced by a new version, until their tasks are
+     * over */
+    pub retiring: Vec<TaskGroup>,
     pub dirty: bool
 }
 
@@ -17,13 +36,49 @@ impl Serialize for Environment {
     where S: Serializer {
         #[derive(Serialize)]
         struct SerializedEnvironment<'a> {
-            pub groups: &'a Vec<TaskGroup>,
+            pub groups: Vec<&'a TaskGroup>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            pub submitted_groups: Vec<&'a TaskGroup>,
             pub log: &'a Option<PathBuf>,
+            pub cgroup: &'a Option<PathBuf>,
+            pub secrets: &'a Option<SecretStore>,
+            pub smtp: &'a Option<SmtpRelay>,
+            #[serde(skip_serializing_if = "HashMap::is_empty")]
+            pub calendars: &'a Calendars,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub at_queue: Option<&'a Path>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub spool_dir: Option<&'a Path>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub include: &'a Option<PathBuf>,
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            pub included_state: BTreeMap<&'a str, GroupState>,
         }
 
+        /* The included groups stay in their files, but not their state */
+        let mut included_state: BTreeMap<&str, GroupState> = self.included_state.iter()
+            .map(|(name, state)| (name.as_str(), state.clone()))
+            .collect();
+        included_state.extend(self.groups.iter()
+            .filter(|group| matches!(group.provenance(), Provenance::Include(_)))
+            .map(|group| (group.name(), group.state())));
+
         SerializedEnvironment {
-            groups: &self.groups,
+            groups: self.groups.iter()
+                .filter(|group| *group.provenance() == Provenance::Config)
+                .collect(),
+            submitted_groups: self.groups.iter()
+                .filter(|group| *group.provenance() == Provenance::Api)
+                .collect(),
             log: &self.log,
+            cgroup: &self.cgroup,
+            secrets: &self.secrets,
+            smtp: &self.smtp,
+            calendars: &self.calendars,
+            at_queue: self.at.path(),
+            spool_dir: self.spool.as_ref().map(|x| x.dir()),
+            include: &self.include,
+            included_state,
         }.serialize(serializer)
     }
 }
@@ -31,9 +86,17 @@ impl Serialize for Environment {
 impl Environment {
     pub fn update(&mut self) {
         debug!("[ENV] Update");
+        self.process_spool();
         for group in self.groups.iter_mut() {
             group.update();
+            self.dirty |= group.take_dirty();
         }
+        self.remove_finished_groups();
+        for group in self.retiring.iter_mut() {
+            group.update();
+        }
+        self.retiring.retain(|group| !group.is_finished());
+        self.at.update();
 
         if self.dirty {
             //TODO: Change
@@ -48,31 +111,304 @@ impl Environment {
         }
     }
 
-    fn get_task_group_log_path(path: &PathBuf, id: usize) -> PathBuf{
-        path.join(id.to_string())
+    /* The directories of a group are named after it, so that they stay the
+     * same whatever the groups before it. The names are escaped, except
+     * for ASCII letters, digits, dashes and underscores. */
+    fn get_task_group_path(path: &Path, name: &str) -> PathBuf {
+        let mut key = String::new();
+        for byte in name.bytes() {
+            match byte {
+            b'a' ..= b'z' | b'A' ..= b'Z' | b'0' ..= b'9' | b'-' | b'_' => key.push(byte as char),
+            _ => key.push_str(&format!("%{:02X}", byte))
+            }
+        }
+        path.join(GROUPS_DIR).join(key)
     }
 
-    pub fn add_new_group(&mut self, mut task_group: TaskGroup) {
-        let id = self.groups.len();
+    /* Gives the group the settings of the environment */
+    fn configure_group(&self, group: &mut TaskGroup, log_path: Option<PathBuf>, cgroup_path: Option<PathBuf>) {
+        if let Some(path) = log_path {
+            group.set_log_path(path);
+        }
+
+        if let Some(path) = cgroup_path {
+            group.set_cgroup_path(path);
+        }
+
+        if let Some(store) = &self.secrets {
+            group.set_secret_store(store.clone());
+        }
+
+        if let Some(relay) = &self.smtp {
+            group.set_smtp_relay(relay.clone());
+        }
+
+        group.set_calendars(self.calendars.clone());
+    }
+
+    fn process_spool(&mut self) {
+        let Some(mut spool) = self.spool.take() else {
+            return;
+        };
+
+        for path in spool.take_files() {
+            let result = Spool::read(&path)
+                .and_then(|stg| self.submit_group(stg));
+            spool.done(&path, result);
+        }
+        self.spool = Some(spool);
+    }
+
+    pub fn check_calendars<'a>(&self, mut names: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        match names.find(|name| !self.calendars.contains_key(*name)) {
+        Some(name) => Err(format!("Unknown calendar: {}", name)),
+        None => Ok(())
+        }
+    }
+
+    /* Checks the group before adding it. A group with the name of an
+     * earlier submission replaces it, the others keep their names */
+    pub fn submit_group(&mut self, stg: SerializedTaskGroup) -> Result<(), String> {
+        let existing = self.groups.iter().position(|x| x.name() == stg.name());
+        let checked = stg.validate()
+            .and_then(|_| self.check_calendars(stg.calendars()));
+        let checked = checked.and_then(|_| match existing.map(|id| &self.groups[id]) {
+            Some(other) if *other.provenance() != Provenance::Api =>
+                Err(format!("\"{}\" is already declared in {}", stg.name(), other.provenance())),
+            _ => Ok(())
+        });
+        if let Err(e) = checked {
+            error!("[ENV] Rejected a new task group: {}", e);
+            return Err(e);
+        }
 
-        if let Some(path) = &self.log {
-            let group_path = Self::get_task_group_log_path(&path, id);
-            task_group.set_log_path(group_path);
+        let mut group = TaskGroup::from(stg);
+        group.set_provenance(Provenance::Api);
+        match existing {
+        Some(id) => {
+            info!("[ENV] Replacing the task group \"{}\"", group.name());
+            self.configure_new_group(&mut group);
+            group.take_over(&mut self.groups[id]);
+            let old = std::mem::replace(&mut self.groups[id], group);
+            self.retiring.push(old);
+            self.dirty = true;
+        },
+        None => self.add_new_group(group)
         }
+        Ok(())
+    }
+
+    fn configure_new_group(&self, task_group: &mut TaskGroup) {
+        let log_path = self.log.as_ref()
+            .map(|path| Self::get_task_group_path(path, task_group.name()));
+        let cgroup_path = self.cgroup.as_ref()
+            .map(|path| Self::get_task_group_path(path, task_group.name()));
 
+        self.configure_group(task_group, log_path, cgroup_path);
+    }
+
+    pub fn add_new_group(&mut self, mut task_group: TaskGroup) {
+        self.configure_new_group(&mut task_group);
         self.groups.push(task_group);
         self.dirty = true;
     }
 
-    pub fn set_log_path(&mut self, path: PathBuf) {
-        if !path.exists() {
-            std::fs::create_dir(&path).unwrap();
+    /* The one-shot jobs log in the "at" directory, by id */
+    fn configure_at_job(&self, group: &mut TaskGroup, id: u64) {
+        let log_path = self.log.as_ref()
+            .map(|path| path.join("at"))
+            .filter(|path| match fs::create_dir_all(path) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("[AT] Unable to create {:?}: {}", path, e);
+                    false
+                }
+            })
+            .map(|path| path.join(id.to_string()));
+        let cgroup_path = self.cgroup.as_ref()
+            .map(|path| path.join(format!("at-{}", id)));
+
+        self.configure_group(group, log_path, cgroup_path);
+    }
+
+    pub fn set_at_queue(&mut self, mut queue: AtQueue) {
+        for job in queue.jobs.iter_mut() {
+            self.configure_at_job(&mut job.group, job.id);
         }
+        self.at = queue;
+    }
 
-        for (id, group) in self.groups.iter_mut().enumerate() {
-            let group_path = Self::get_task_group_log_path(&path, id);
+    pub fn queue_at(&mut self, request: AtRequest) -> Result<u64, String> {
+        let mut queue = std::mem::take(&mut self.at);
+        let result = queue.push(request)
+            .map(|job| {
+                self.configure_at_job(&mut job.group, job.id);
+                job.id
+            });
+        self.at = queue;
+        result
+    }
+
+    fn remove_finished_groups(&mut self) {
+        let len = self.groups.len();
+        let included_state = &mut self.included_state;
+        self.groups.retain(|group| {
+            if group.is_finished() {
+                info!("[ENV] Removing the expired group \"{}\"", group.name());
+                /* Or it would come back from its file on restart */
+                if let Provenance::Include(_) = group.provenance() {
+                    included_state.insert(group.name().to_owned(), group.state());
+                }
+            }
+            !group.is_finished()
+        });
+        self.dirty |= self.groups.len() != len;
+    }
+
+    pub fn set_log_path(&mut self, path: PathBuf) {
+        std::fs::create_dir_all(path.join(GROUPS_DIR)).unwrap();
+
+        for group in self.groups.iter_mut() {
+            let group_path = Self::get_task_group_path(&path, group.name());
             group.set_log_path(group_path);
         }
         self.log = Some(path);
     }
-}
\ No newline at end of file
+
+    pub fn set_cgroup_path(&mut self, path: PathBuf) {
+        if let Err(e) = cgroup::setup_root(&path).and_then(|_| cgroup::create_parent(&path.join(GROUPS_DIR))) {
+            error!("[ENV] Unable to use {:?} as the cgroup root: {}", path, e);
+            return;
+        }
+
+        for group in self.groups.iter_mut() {
+            group.set_cgroup_path(Self::get_task_group_path(&path, group.name()));
+        }
+        self.cgroup = Some(path);
+    }
+
+    pub fn set_secret_store(&mut self, store: SecretStore) {
+        for group in self.groups.iter_mut() {
+            group.set_secret_store(store.clone());
+        }
+        self.secrets = Some(store);
+    }
+
+    pub fn set_smtp_relay(&mut self, relay: SmtpRelay) {
+        for group in self.groups.iter_mut() {
+            group.set_smtp_relay(relay.clone());
+        }
+        self.smtp = Some(relay);
+    }
+
+    /* The calendars must have been loaded */
+    pub fn set_calendars(&mut self, calendars: Calendars) {
+        self.calendars = Arc::new(calendars);
+        for group in self.groups.iter_mut() {
+            group.set_calendars(self.calendars.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use common::include;
+    use serde_json::json;
+
+    use super::*;
+
+    fn environment(spool: Spool) -> Environment {
+        Environment {
+            groups: Vec::new(),
+            log: None,
+            cgroup: None,
+            secrets: None,
+            smtp: None,
+            calendars: Default::default(),
+            at: AtQueue::default(),
+            spool: Some(spool),
+            include: None,
+            included_state: BTreeMap::new(),
+            retiring: Vec::new(),
+            dirty: false
+        }
+    }
+
+    fn group(name: &str, starts_at: &str) -> String {
+        json!({
+            "name": name,
+            "starts_at": starts_at,
+            "period": null,
+            "processes": [{"cmd": {"script": "true", "chdir": "/"}}]
+        }).to_string()
+    }
+
+    /* The malformed files are rejected, instead of taking the server down
+     * each time it reads the spool */
+    #[test]
+    fn test_spool_submit() {
+        let dir = std::env::temp_dir().join(format!("scheduler-spool-env-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("a.json"), group("a", "2099-01-01T00:00:00Z")).unwrap();
+        fs::write(dir.join("b.json"), group("b", "2026-13-01T00:00:00Z")).unwrap();
+        fs::write(dir.join("c.json"), group("c", "2026-11-01T03:0")).unwrap();
+        fs::write(dir.join("d.json"), group("d", "2026-11-01T03:00:0é")).unwrap();
+        fs::write(dir.join("e.json"), group("a", "2098-01-01T00:00:00Z")).unwrap();
+
+        let mut env = environment(Spool::new(dir.clone()).unwrap());
+        env.process_spool();
+
+        /* The second "a" replaced the first */
+        assert_eq!(env.groups.len(), 1);
+        assert_eq!(env.groups[0].name(), "a");
+        assert_eq!(*env.groups[0].provenance(), Provenance::Api);
+        assert_eq!(env.retiring.len(), 1);
+        for name in ["a.json", "e.json"] {
+            assert!(dir.join("processed").join(name).exists());
+        }
+        for name in ["b.json", "c.json", "d.json"] {
+            assert!(dir.join("rejected").join(name).exists());
+            assert!(dir.join("rejected").join(format!("{}.error", name)).exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /* The included groups are not saved, but their state is, even once
+     * they are removed */
+    #[test]
+    fn test_included_state() {
+        let dir = std::env::temp_dir().join(format!("scheduler-included-state-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut once: serde_json::Value = serde_json::from_str(&group("once", "****-**-**T**:**:**Z")).unwrap();
+        once["period"] = json!("PT1S");
+        once["max_runs"] = json!(1);
+        once["expire_action"] = json!("remove");
+        fs::write(dir.join("once.json"), once.to_string()).unwrap();
+        fs::write(dir.join("later.json"), group("later", "2099-01-01T00:00:00Z")).unwrap();
+
+        let mut env = environment(Spool::new(dir.join("spool")).unwrap());
+        env.groups = include::load(&dir).unwrap();
+        for _ in 0 .. 300 {
+            env.groups.iter_mut().for_each(|group| { group.update(); });
+            if env.groups.iter().any(|group| group.is_finished()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        env.remove_finished_groups();
+        assert_eq!(env.groups.len(), 1);
+
+        let saved = serde_json::to_value(&env).unwrap();
+        assert_eq!(saved["groups"], json!([]));
+        assert_eq!(saved["included_state"]["once"], json!({"runs": 1, "expired": true}));
+        assert_eq!(saved["included_state"]["later"], json!({}));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
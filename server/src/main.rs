@@ -1,18 +1,21 @@
-use std::{env, io::{self, Error, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, path::PathBuf, sync::{Arc, RwLock}, thread, time::Duration};
+use std::{collections::BTreeMap, env, io::{self, Error, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, path::PathBuf, sync::{Arc, RwLock}, thread, time::Duration};
 
 use log::{error, info, LevelFilter};
 
-use common::{group::TaskGroup, log::SimpleLogger, queries::Queries};
-use serde::{Deserialize, Deserializer};
-use crate::environment::Environment;
+use common::{calendar::Calendars, group::{GroupState, Provenance, TaskGroup}, include, log::SimpleLogger, notify::SmtpRelay, queries::{Queries, TaskStatisticReport}, secrets::SecretStore, spool::Spool};
+use serde::{de, Deserialize, Deserializer};
+use crate::{at::AtQueue, environment::Environment, metrics::{metrics_handler, task_label}};
 
+mod at;
 mod environment;
+mod metrics;
 
 pub static LOGGER: SimpleLogger = SimpleLogger;
 
 pub struct Server {
     env: Arc<RwLock<Environment>>,
-    listener: Option<TcpListener>
+    listener: Option<TcpListener>,
+    metrics: Option<TcpListener>
 }
 
 impl<'de> Deserialize<'de> for Server {
@@ -21,19 +24,105 @@ impl<'de> Deserialize<'de> for Server {
         #[derive(Deserialize)]
         pub struct EnvironmentJson {
             log: Option<PathBuf>,
+            cgroup: Option<PathBuf>,
+            secrets: Option<SecretStore>,
+            smtp: Option<SmtpRelay>,
+            #[serde(default)]
+            calendars: Calendars,
+            at_queue: Option<PathBuf>,
+            spool_dir: Option<PathBuf>,
+            include: Option<PathBuf>,
             listening: Option<String>,
-            groups: Vec<TaskGroup>
+            metrics: Option<String>,
+            groups: Vec<TaskGroup>,
+            #[serde(default)]
+            submitted_groups: Vec<TaskGroup>,
+            #[serde(default)]
+            included_state: BTreeMap<String, GroupState>
+        }
+
+        let mut val = EnvironmentJson::deserialize(deserializer)?;
+        for group in val.submitted_groups.iter_mut() {
+            group.set_provenance(Provenance::Api);
+        }
+
+        let mut included = match &val.include {
+            Some(path) => include::load(path).map_err(de::Error::custom)?,
+            None => Vec::new()
+        };
+        /* The state of the groups no file declares anymore is dropped */
+        let mut included_state = BTreeMap::new();
+        for group in included.iter_mut() {
+            if let Some(state) = val.included_state.remove(group.name()) {
+                group.restore_state(state.clone());
+                included_state.insert(group.name().to_owned(), state);
+            }
+        }
+
+        /* The groups are told apart by their names */
+        let mut groups: Vec<TaskGroup> = Vec::new();
+        for group in val.groups.into_iter().chain(val.submitted_groups).chain(included) {
+            if let Some(other) = groups.iter().find(|x| x.name() == group.name()) {
+                return Err(de::Error::custom(format!(
+                    "{}: \"{}\" is already declared in {}",
+                    group.provenance(), group.name(), other.provenance()
+                )));
+            }
+            groups.push(group);
         }
 
-        let val = EnvironmentJson::deserialize(deserializer)?;
         let mut output_env = Environment {
-            groups: val.groups,
+            groups,
             log: None,
+            cgroup: None,
+            secrets: None,
+            smtp: None,
+            calendars: Default::default(),
+            at: AtQueue::default(),
+            spool: None,
+            include: val.include,
+            included_state,
+            retiring: Vec::new(),
 			dirty: false
         };
         if let Some(path) = val.log {
             output_env.set_log_path(path);
         }
+        if let Some(path) = val.cgroup {
+            output_env.set_cgroup_path(path);
+        }
+        if let Some(store) = val.secrets {
+            output_env.set_secret_store(store);
+        }
+        if let Some(relay) = val.smtp {
+            output_env.set_smtp_relay(relay);
+        }
+
+        let mut calendars = val.calendars;
+        for (name, calendar) in calendars.iter_mut() {
+            calendar.load()
+                .map_err(|e| de::Error::custom(format!("Calendar \"{}\": {}", name, e)))?;
+        }
+        output_env.set_calendars(calendars);
+        for group in output_env.groups.iter() {
+            output_env.check_calendars(group.calendar_names())
+                .map_err(|e| de::Error::custom(format!("Group \"{}\" of {}: {}", group.name(), group.provenance(), e)))?;
+        }
+
+        let at_queue = val.at_queue
+            .or(output_env.log.as_ref().map(|path| path.join("at.json")));
+        if let Some(path) = at_queue {
+            let queue = AtQueue::load(path.clone())
+                .map_err(|e| de::Error::custom(format!("{:?}: {}", path, e)))?;
+            output_env.set_at_queue(queue);
+        }
+
+        if let Some(path) = val.spool_dir {
+            let spool = Spool::new(path.clone())
+                .map_err(|e| de::Error::custom(format!("{:?}: {}", path, e)))?;
+            info!("Watching {:?} for new task groups", path);
+            output_env.spool = Some(spool);
+        }
 
 		let listener = val.listening
 			.map(|addr| {
@@ -42,26 +131,84 @@ impl<'de> Deserialize<'de> for Server {
 				out
 			});
 
+		let metrics = val.metrics
+			.map(|addr| {
+				let out = TcpListener::bind(&addr).expect("Unable to serve the metrics");
+				info!("Serving the metrics on {}", addr);
+				out
+			});
+
         Ok(Server {
 			env: Arc::new(RwLock::new(output_env)),
-			listener: listener
+			listener,
+			metrics
 		})
     }
 }
 
+fn reply(stream: &mut TcpStream, answer: &Queries) -> io::Result<()> {
+	stream.write_all(
+		serde_json::to_vec(answer)
+			.unwrap()
+			.as_slice()
+	)
+}
+
 fn query_handler(query: Queries, stream: &mut TcpStream, env: Arc<RwLock<Environment>>) -> io::Result<()> {
 	match query {
-	Queries::Ok => Ok(()),
+	Queries::Ok |
+	Queries::Error(_) |
+	Queries::Statistics(_) |
+	Queries::AtQueued(_) |
+	Queries::AtJobs(_) => Ok(()),
+	Queries::At(request) => {
+		let mut env = env.write()
+			.expect("Unable to write to env");
+		match env.queue_at(*request) {
+		Ok(id) => reply(stream, &Queries::AtQueued(id)),
+		Err(e) => {
+			error!("[AT] Rejected a job: {}", e);
+			reply(stream, &Queries::Error(e))
+		}
+		}
+	},
+	Queries::ListAt => {
+		let env = env.read()
+			.expect("Unable to read env");
+		reply(stream, &Queries::AtJobs(env.at.list()))
+	},
+	Queries::CancelAt(id) => {
+		let mut env = env.write()
+			.expect("Unable to write to env");
+		match env.at.cancel(id) {
+		Ok(()) => reply(stream, &Queries::Ok),
+		Err(e) => reply(stream, &Queries::Error(e))
+		}
+	},
 	Queries::NewTaskGroup(stg) => {
 		let mut env = env.write()
 			.expect("Unable to write to env");
-		env.add_new_group(TaskGroup::from(stg));
-		stream.write_all(
-			serde_json::to_vec(
-				&Queries::Ok
-			).unwrap()
-			.as_slice()
-		)
+		match env.submit_group(*stg) {
+		Ok(()) => reply(stream, &Queries::Ok),
+		Err(e) => reply(stream, &Queries::Error(e))
+		}
+	},
+	Queries::GetStatistics(name) => {
+		let env = env.read()
+			.expect("Unable to read env");
+		let reports = env.groups.iter()
+			.filter(|group| name.as_ref().is_none_or(|name| name == group.name()))
+			.flat_map(|group|
+				group.tasks().iter()
+					.enumerate()
+					.map(|(id, task)| TaskStatisticReport {
+						group: group.name().to_string(),
+						task: task_label(task, id),
+						statistics: task.stats().summary()
+					})
+			)
+			.collect();
+		reply(stream, &Queries::Statistics(reports))
 	}
 	}
 }
@@ -80,6 +227,7 @@ fn network_handler(listener: TcpListener, env: Arc<RwLock<Environment>>) {
 				Ok(query) => query_handler(query, &mut stream, env),
 				Err(e) => {
 					error!("[ENV] Error while parsing data: {}", e);
+					reply(&mut stream, &Queries::Error(e.to_string()))?;
 					Err(Error::from(ErrorKind::InvalidData))
 				}
 				}
@@ -99,7 +247,7 @@ fn main() {
 	}
 
 	let server: Server = serde_json::from_str(
-			&String::from_utf8(
+			String::from_utf8(
 				std::fs::read(&conf_path[1])
 					.unwrap()
 			).unwrap()
@@ -110,9 +258,54 @@ fn main() {
 		let env = server.env.clone();
 		network_handler(listener, env);
 	}
+
+	if let Some(listener) = server.metrics {
+		metrics_handler(listener, server.env.clone());
+	}
 	
 	loop {
 		server.env.write().unwrap().update();
 		thread::sleep(Duration::from_millis(500));
 	}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /* The included groups pick up where they were */
+    #[test]
+    fn test_included_state() {
+        let dir = std::env::temp_dir().join(format!("scheduler-include-load-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("once.json"), json!({
+            "name": "once",
+            "starts_at": "****-**-**T**:**:**Z",
+            "period": "PT1S",
+            "max_runs": 1,
+            "processes": [{"cmd": {"script": "true", "chdir": "/"}}]
+        }).to_string()).unwrap();
+
+        let server: Server = serde_json::from_value(json!({
+            "groups": [],
+            "include": dir,
+            "included_state": {
+                "once": {"runs": 1, "expired": true},
+                "gone": {"runs": 3}
+            }
+        })).unwrap();
+        let env = server.env.read().unwrap();
+        assert_eq!(env.groups.len(), 1);
+        assert_eq!(env.groups[0].runs(), 1);
+        assert!(env.groups[0].is_expired());
+        assert_eq!(env.groups[0].next_execution(), None);
+        assert_eq!(env.included_state.keys().collect::<Vec<_>>(), ["once"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
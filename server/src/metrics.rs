@@ -0,0 +1,137 @@
+use std::{fs, io::{self, BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, sync::{Arc, RwLock}, thread};
+
+use log::error;
+
+use common::{group::TaskGroup, metrics::Exposition, task::Task};
+use crate::environment::Environment;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub fn task_label(task: &Task, id: usize) -> String {
+    task.name().unwrap_or(id.to_string())
+}
+
+/* Writes one sample per task of every group */
+fn task_family<F>(out: &mut Exposition, groups: &[TaskGroup], name: &str, kind: &str, help: &str, value: F)
+where F: Fn(&Task) -> Option<f64> {
+    out.family(name, kind, help);
+    for group in groups.iter() {
+        for (id, task) in group.tasks().iter().enumerate() {
+            if let Some(value) = value(task) {
+                let task_name = task_label(task, id);
+                out.sample(name, &[("group", group.name()), ("task", &task_name)], value);
+            }
+        }
+    }
+}
+
+fn nb_threads() -> Option<f64> {
+    fs::read_to_string("/proc/self/status").ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+pub fn render(env: &Environment) -> String {
+    let mut out = Exposition::new();
+    let groups = &env.groups;
+
+    task_family(&mut out, groups, "scheduler_task_runs_total", "counter",
+        "Executions started, retries included", |t| Some(t.metrics().runs as f64));
+    task_family(&mut out, groups, "scheduler_task_failures_total", "counter",
+        "Executions that did not succeed", |t| Some(t.metrics().failures as f64));
+    task_family(&mut out, groups, "scheduler_task_timeouts_total", "counter",
+        "Executions killed after their timeout", |t| Some(t.metrics().timeouts as f64));
+    task_family(&mut out, groups, "scheduler_task_skipped_total", "counter",
+        "Executions not started because too many were running", |t| Some(t.metrics().skipped as f64));
+    task_family(&mut out, groups, "scheduler_task_running", "gauge",
+        "Executions currently running", |t| Some(t.nb_running_tasks() as f64));
+    task_family(&mut out, groups, "scheduler_task_last_success_timestamp_seconds", "gauge",
+        "Scheduled time of the last successful execution",
+        |t| t.last_success().map(|x| x.timestamp() as f64));
+
+    let name = "scheduler_task_duration_seconds";
+    out.family(name, "histogram", "Duration of the executions");
+    for group in groups.iter() {
+        for (id, task) in group.tasks().iter().enumerate() {
+            let task_name = task_label(task, id);
+            out.histogram(name, &[("group", group.name()), ("task", &task_name)],
+                &task.metrics().duration);
+        }
+    }
+
+    let name = "scheduler_group_runs_total";
+    out.family(name, "counter", "Times the group was launched");
+    for group in groups.iter() {
+        out.sample(name, &[("group", group.name())], group.runs() as f64);
+    }
+
+    let name = "scheduler_group_next_fire_timestamp_seconds";
+    out.family(name, "gauge", "Next time the group is launched");
+    for group in groups.iter() {
+        if let Some(next) = group.next_execution() {
+            out.sample(name, &[("group", group.name())], next.timestamp() as f64);
+        }
+    }
+
+    out.family("scheduler_groups", "gauge", "Task groups loaded");
+    out.sample("scheduler_groups", &[], groups.len() as f64);
+
+    out.family("scheduler_queue_depth", "gauge", "Retries waiting for their delay");
+    out.sample("scheduler_queue_depth", &[],
+        groups.iter()
+            .flat_map(|group| group.tasks())
+            .map(|task| task.nb_pending_retries())
+            .sum::<usize>() as f64
+    );
+
+    if let Some(threads) = nb_threads() {
+        out.family("scheduler_threads", "gauge", "Threads of the server process");
+        out.sample("scheduler_threads", &[], threads);
+    }
+
+    out.finish()
+}
+
+fn handle(stream: TcpStream, env: Arc<RwLock<Environment>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    /* Skip the headers */
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+    (Some("GET"), Some("/metrics")) =>
+        ("200 OK", render(&env.read().expect("Unable to read env"))),
+    (Some("GET"), _) => ("404 Not Found", String::from("Not found\n")),
+    _ => ("405 Method Not Allowed", String::from("Method not allowed\n"))
+    };
+
+    let mut stream = stream;
+    write!(stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, CONTENT_TYPE, body.len(), body
+    )
+}
+
+pub fn metrics_handler(listener: TcpListener, env: Arc<RwLock<Environment>>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let env = env.clone();
+            thread::spawn(move || {
+                if let Err(e) = stream.and_then(|stream| handle(stream, env)) {
+                    error!("[METRICS] Unable to answer: {}", e);
+                }
+            });
+        }
+    });
+}